@@ -1,8 +1,15 @@
 extern crate askl;
 
+use askl::cfg::ControlFlowGraph;
+use askl::output::Format;
+use askl::symbols::SymbolMap;
 use askl::Askl;
 
-fn main() {
-    Askl::new("{}");
-    Askl::new("\"ib_vesd\" {}");
+fn main() -> anyhow::Result<()> {
+    let index = std::env::args().nth(1).unwrap_or_else(|| "symbol_map.idx".to_string());
+    let cfg = ControlFlowGraph::from_symbols(SymbolMap::load(index)?);
+
+    Askl::new("{}")?.run(&cfg).write(Format::Tree, &mut std::io::stdout())?;
+    Askl::new("\"ib_vesd\" {}")?.run(&cfg).write(Format::Tree, &mut std::io::stdout())?;
+    Ok(())
 }
@@ -0,0 +1,213 @@
+use crate::cfg::{ControlFlowGraph, EdgeKinds, EdgeList};
+use crate::query::QueryResult;
+use crate::symbols::{name_matches, SymbolId};
+use anyhow::{anyhow, Context, Result};
+use petgraph::algo::tarjan_scc;
+use petgraph::visit::{Bfs, EdgeFiltered, EdgeRef, Walker};
+use regex::Regex;
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::str::FromStr;
+
+/// Functions a program or library is entered through
+///
+/// A configuration file lists one entry point per line, either a name or a
+/// regex between slashes:
+///
+/// ```text
+/// # Comment
+/// main
+/// ns::Server::run
+/// /^test_/
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct EntryPoints {
+    /// Plain or qualified names, or symbol ids
+    pub names: Vec<String>,
+    /// Patterns matching plain or qualified names
+    pub patterns: Vec<Regex>,
+    /// Every symbol with external linkage is an entry point, as in a library
+    pub exported: bool,
+    /// Functions whose address is taken are only live when the code taking
+    /// it is. Otherwise they count as entry points, since calls through
+    /// pointers are not followed in general.
+    pub strict: bool,
+}
+
+impl EntryPoints {
+    /// Add the entry points of a configuration file
+    pub fn load<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path).with_context(|| format!("Cannot read entry points {:?}", path))?;
+        let config: EntryPoints = text.parse().with_context(|| format!("Invalid entry points {:?}", path))?;
+        self.names.extend(config.names);
+        self.patterns.extend(config.patterns);
+        Ok(())
+    }
+
+    /// Whether nothing but address-taken functions would be an entry point
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty() && self.patterns.is_empty() && !self.exported
+    }
+
+    fn matches(&self, cfg: &ControlFlowGraph, id: &SymbolId) -> bool {
+        let (name, scope) = match cfg.symbol_name(id) {
+            Some(name) => name,
+            None => return false,
+        };
+        let qualified = scope.map(|scope| format!("{}::{}", scope, name));
+        self.names
+            .iter()
+            .any(|entry| entry == id.as_str() || name_matches(name, scope, entry))
+            || self.patterns.iter().any(|pattern| {
+                pattern.is_match(name) || qualified.as_ref().is_some_and(|qualified| pattern.is_match(qualified))
+            })
+            || (self.exported && cfg.is_exported(id))
+    }
+
+    /// Defined symbols the analysis starts from
+    pub fn roots(&self, cfg: &ControlFlowGraph) -> Vec<SymbolId> {
+        let stored = if self.strict { HashSet::new() } else { cfg.stored_functions() };
+        cfg.symbol_ids()
+            .filter(|id| {
+                self.matches(cfg, id) || (!self.strict && (stored.contains(id) || cfg.is_address_taken(id)))
+            })
+            .cloned()
+            .collect()
+    }
+}
+
+impl FromStr for EntryPoints {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut entries = EntryPoints::default();
+        for (number, line) in s.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            match line.strip_prefix('/').and_then(|line| line.strip_suffix('/')) {
+                Some(regex) => entries.patterns.push(
+                    Regex::new(regex).map_err(|e| anyhow!("Line {}: invalid regex \"{}\": {}", number + 1, regex, e))?,
+                ),
+                None => entries.names.push(line.to_string()),
+            }
+        }
+        Ok(entries)
+    }
+}
+
+/// Symbols defined in the project that no reference of any kind leads to
+/// from the entry points, sorted by id
+///
+/// Symbols only declared have no code to remove, and neither do the ones
+/// defined in system headers, which lie outside the project root.
+pub fn dead_symbols(cfg: &ControlFlowGraph, entries: &EntryPoints) -> Vec<SymbolId> {
+    let graph = cfg.graph();
+    let mut live = HashSet::new();
+    for root in entries.roots(cfg).iter().filter_map(|id| cfg.node_id(id)) {
+        if !live.contains(&root) {
+            live.extend(Bfs::new(graph, root).iter(graph));
+        }
+    }
+
+    let mut dead: Vec<SymbolId> = cfg
+        .symbol_ids()
+        .filter(|id| cfg.node_id(id).is_some_and(|node| !live.contains(&node)))
+        .filter(|id| cfg.definition_file(id).is_some_and(|file| cfg.project_path(file).is_relative()))
+        .cloned()
+        .collect();
+    dead.sort();
+    dead
+}
+
+/// Group of the dead symbols nothing but themselves refers to
+pub const UNREFERENCED: &str = "never referenced";
+/// Group of the dead symbols only other dead symbols refer to
+pub const UNREACHABLE: &str = "unreachable";
+
+/// Dead symbols and the references between them, in the group
+/// [`UNREFERENCED`] or [`UNREACHABLE`]
+///
+/// Dead symbols referenced only by other dead symbols are the callees of the
+/// ones never referenced at all, or lie on cycles.
+pub fn dead_code(cfg: &ControlFlowGraph, entries: &EntryPoints) -> QueryResult {
+    let dead = dead_symbols(cfg, entries);
+    let set: HashSet<&SymbolId> = dead.iter().collect();
+    let mut edges: Vec<(SymbolId, SymbolId)> = dead
+        .iter()
+        .flat_map(|id| {
+            cfg.neighbors(id)
+                .into_iter()
+                .filter(|callee| set.contains(callee))
+                .map(move |callee| (id.clone(), callee))
+        })
+        .collect();
+    edges.sort();
+
+    let mut result = QueryResult::new(cfg, &dead, &EdgeList(edges));
+    for symbol in result.symbols.iter_mut() {
+        let referenced = cfg.predecessors(&symbol.id).iter().any(|caller| *caller != symbol.id);
+        symbol.group = Some(if referenced { UNREACHABLE } else { UNREFERENCED }.to_string());
+    }
+    result
+}
+
+/// Functions calling each other, directly or through the other members
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cycle {
+    /// Members, sorted by id
+    pub symbols: Vec<SymbolId>,
+    /// References between the members, sorted
+    pub edges: Vec<(SymbolId, SymbolId)>,
+}
+
+/// Recursive functions and groups of mutually recursive functions, following
+/// references of the given kinds only
+///
+/// These are the strongly connected components of the graph with more than
+/// one member, or with a member calling itself. They are sorted by their
+/// first member.
+pub fn cycles(cfg: &ControlFlowGraph, kinds: EdgeKinds) -> Vec<Cycle> {
+    let graph = EdgeFiltered::from_fn(cfg.graph(), |edge| edge.weight().intersects(kinds));
+    let mut cycles: Vec<Cycle> = tarjan_scc(&graph)
+        .into_iter()
+        .map(|component| {
+            let members: HashSet<_> = component.iter().copied().collect();
+            let mut edges: Vec<(SymbolId, SymbolId)> = component
+                .iter()
+                .flat_map(|node| cfg.graph().edges(*node))
+                .filter(|edge| edge.weight().intersects(kinds) && members.contains(&edge.target()))
+                .map(|edge| (cfg.symbol_id(edge.source()).clone(), cfg.symbol_id(edge.target()).clone()))
+                .collect();
+            edges.sort();
+            let mut symbols: Vec<SymbolId> = component.into_iter().map(|node| cfg.symbol_id(node).clone()).collect();
+            symbols.sort();
+            Cycle { symbols, edges }
+        })
+        // A single function has edges to itself only if it is recursive
+        .filter(|cycle| cycle.symbols.len() > 1 || !cycle.edges.is_empty())
+        .collect();
+    cycles.sort_by(|a, b| a.symbols.cmp(&b.symbols));
+    cycles
+}
+
+/// Members and edges of all cycles, the members of the n-th cycle forming the
+/// group `cycle n`
+pub fn cycle_report(cfg: &ControlFlowGraph, kinds: EdgeKinds) -> QueryResult {
+    let cycles = cycles(cfg, kinds);
+    let groups: HashMap<&SymbolId, String> = cycles
+        .iter()
+        .enumerate()
+        .flat_map(|(i, cycle)| cycle.symbols.iter().map(move |id| (id, format!("cycle {}", i + 1))))
+        .collect();
+    let symbols: Vec<SymbolId> = groups.keys().map(|id| (*id).clone()).collect();
+    let edges = cycles.iter().flat_map(|cycle| cycle.edges.iter().cloned()).collect();
+
+    let mut result = QueryResult::new(cfg, &symbols, &EdgeList(edges));
+    for symbol in result.symbols.iter_mut() {
+        symbol.group = groups.get(&symbol.id).cloned();
+    }
+    result
+}
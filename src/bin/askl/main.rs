@@ -1,100 +1,299 @@
-use anyhow::Result;
-use askl::cfg::ControlFlowGraph;
-use clap::Parser;
-use env_logger;
+use anyhow::{bail, Result};
+use askl::cfg::{ControlFlowGraph, EdgeKinds, EdgeList};
+use clap::{Parser, Subcommand};
 use console::{style, Emoji};
 use indicatif::ProgressBar;
 use log::debug;
-use petgraph::dot::{Config, Dot};
+use regex::Regex;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
 
-use askl::parser::parse;
-use askl::symbols::{SymbolMap, SymbolId};
-use petgraph::prelude::DiGraphMap;
+use askl::analysis::{self, EntryPoints};
+use askl::group::Grouping;
+use askl::output::Format;
+use askl::server;
+use askl::symbols::{SymbolId, SymbolMap};
+use askl::{Askl, QueryResult};
+
+mod repl;
 
 /// Indexer for askl
 #[derive(Parser, Debug)]
 #[clap(author, version, about, long_about = None)]
 struct Args {
-    // Path to the index file
-    #[clap(short, long)]
+    /// Path to the index file, binary or JSON
+    #[clap(short, long, default_value = "symbol_map.idx")]
     index: String,
 
-    // Query to process
+    // Query to process, an interactive shell is started without one
     #[clap(value_name = "QUERY", index = 1)]
-    query: String,
+    query: Option<String>,
+
+    /// Output format: dot, json, graphml, mermaid, csv or tree
+    #[clap(short, long, default_value = "dot")]
+    format: Format,
+
+    /// File to write the result to, standard output by default
+    #[clap(short, long)]
+    output: Option<String>,
+
+    /// Merge the symbols of the result by file, dir, dir:DEPTH or
+    /// modules:FILE, weighting edges by their number of references
+    #[clap(long, value_name = "GROUPING", conflicts_with = "cluster")]
+    collapse: Option<Grouping>,
+
+    /// Draw the symbols of each group in a cluster of the DOT output,
+    /// groups are given as for --collapse
+    #[clap(long, value_name = "GROUPING")]
+    cluster: Option<Grouping>,
+
+    #[clap(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Find how one function ends up calling another
+    Path {
+        /// Name of the calling function
+        #[clap(long)]
+        from: String,
+
+        /// Name of the called function
+        #[clap(long)]
+        to: String,
+
+        /// Report every simple call chain instead of the shortest one
+        #[clap(long)]
+        all: bool,
+
+        /// Maximal number of calls in a chain reported with --all
+        #[clap(long, default_value = "10")]
+        max_length: usize,
+
+        /// Kinds of references followed, such as call,virtual
+        #[clap(long, default_value = "call,virtual,indirect")]
+        kinds: EdgeKinds,
+    },
+    /// Report the symbols unreachable from the entry points, `main` unless
+    /// others are given
+    Dead {
+        /// Name of an entry point
+        #[clap(long = "entry", value_name = "NAME")]
+        entries: Vec<String>,
+
+        /// Every function whose name matches is an entry point
+        #[clap(long = "regex", value_name = "REGEX")]
+        patterns: Vec<Regex>,
+
+        /// File listing entry points, one name or /regex/ per line
+        #[clap(long)]
+        config: Option<String>,
+
+        /// Every function with external linkage is an entry point
+        #[clap(long)]
+        exported: bool,
+
+        /// Do not take functions whose address is taken for entry points
+        #[clap(long)]
+        strict: bool,
+    },
+    /// Report the recursive functions, each group of functions calling each
+    /// other forming a cluster
+    Cycles {
+        /// Kinds of references followed, such as call,virtual
+        #[clap(long, default_value = "call,virtual,indirect")]
+        kinds: EdgeKinds,
+    },
+    /// Convert the index, to JSON if the path ends in .json and to the
+    /// binary format otherwise
+    Export {
+        #[clap(value_name = "FILE")]
+        output: String,
+    },
+    /// Answer JSON-RPC requests, one per line, on standard input or a socket
+    Serve {
+        /// Listen on a Unix socket at this path instead of standard input,
+        /// on Unix only
+        #[clap(long)]
+        socket: Option<String>,
+    },
 }
 
 static LOOKING_GLASS: Emoji<'_, '_> = Emoji("🔍  ", "");
 static SPARKLE: Emoji<'_, '_> = Emoji("✨ ", ":-)");
-static CLIP: Emoji<'_, '_> = Emoji("🔗  ", "");
 static PAPER: Emoji<'_, '_> = Emoji("📃  ", "");
 
-fn main() -> Result<()> {
-    env_logger::init();
-    let args = Args::parse();
-
-    println!(
-        "{} {}Loading index...",
-        style("[1/4]").bold().dim(),
-        LOOKING_GLASS
-    );
-    let symbols: SymbolMap = serde_json::from_slice(&std::fs::read(args.index)?)?;
-
-    println!(
+fn run_query(cfg: &ControlFlowGraph, query: &str) -> Result<QueryResult> {
+    eprintln!(
         "{} {}Parsing query...",
         style("[2/4]").bold().dim(),
         SPARKLE
     );
-    let ast = parse(&args.query)?;
+    let askl = Askl::new(query)?;
 
-    println!(
-        "{} {}Adding symbols...",
+    eprintln!(
+        "{} {}Running query...",
         style("[3/4]").bold().dim(),
-        CLIP
+        PAPER
     );
 
-    println!(
-        "{} {}Running query...",
-        style("[4/5]").bold().dim(),
+    debug!("Query: {:#?}", askl);
+    let progress_bar = ProgressBar::new(cfg.node_count() as u64);
+    Ok(askl.run_with_progress(cfg, Some(progress_bar)))
+}
+
+fn run_path(
+    cfg: &ControlFlowGraph,
+    from: &str,
+    to: &str,
+    kinds: EdgeKinds,
+    all: bool,
+    max_length: usize,
+) -> Result<QueryResult> {
+    let sources = cfg.find_symbols(from);
+    if sources.is_empty() {
+        bail!("Unknown function: {}", from);
+    }
+    let targets = cfg.find_symbols(to);
+    if targets.is_empty() {
+        bail!("Unknown function: {}", to);
+    }
+
+    eprintln!(
+        "{} {}Searching paths...",
+        style("[2/4]").bold().dim(),
         PAPER
     );
 
-    let sources : Vec<SymbolId> = symbols.iter().map(|(id, _)| id.clone()).collect();
-    let cfg = ControlFlowGraph::from_symbols(symbols);
+    let edges = if all {
+        cfg.all_simple_paths(&sources, &targets, kinds, Some(max_length))
+    } else {
+        cfg.shortest_path(&sources, &targets, kinds)
+            .unwrap_or_else(|| EdgeList(vec![]))
+    };
 
+    // A function reaches itself without any call
+    let reflexive: Vec<SymbolId> = sources.iter().filter(|id| targets.contains(id)).cloned().collect();
+    Ok(QueryResult::new(cfg, &reflexive, &edges))
+}
 
-    debug!("Global scope: {:#?}", ast);
-    println!("Sources: {:#?}", sources.len());
-    let progress_bar = ProgressBar::new(sources.len() as u64);
+fn run_dead(cfg: &ControlFlowGraph, mut entries: EntryPoints, config: Option<String>) -> Result<QueryResult> {
+    if let Some(config) = config {
+        entries.load(config)?;
+    }
+    if entries.is_empty() {
+        entries.names.push("main".to_string());
+    }
 
-    let (res_symbols, res_edges) = ast
-        .matched_symbols(&cfg, &sources, Some(progress_bar))
-        .unwrap();
+    eprintln!(
+        "{} {}Searching dead code...",
+        style("[2/4]").bold().dim(),
+        PAPER
+    );
 
-    println!("Symbols: {:#?}", res_symbols.len());
-    println!("Edges: {:#?}", res_edges.0.len());
-        
-    let mut result_graph : DiGraphMap<&str, ()> = DiGraphMap::new();
-    
-    println!(
-        "{} {}Making graph...",
-        style("[5/5]").bold().dim(),
+    let result = analysis::dead_code(cfg, &entries);
+    let unreferenced: Vec<&str> = result
+        .symbols
+        .iter()
+        .filter(|symbol| symbol.group.as_deref() == Some(analysis::UNREFERENCED))
+        .map(|symbol| symbol.name.as_str())
+        .collect();
+    eprintln!("Never referenced: {}", unreferenced.join(", "));
+    eprintln!("Only referenced by dead code: {}", result.symbols.len() - unreferenced.len());
+    Ok(result)
+}
+
+fn run_cycles(cfg: &ControlFlowGraph, kinds: EdgeKinds) -> QueryResult {
+    eprintln!(
+        "{} {}Searching cycles...",
+        style("[2/4]").bold().dim(),
         PAPER
     );
-    
-    for (from, to) in res_edges.0 {
-        let sym_from = cfg.get_symbol(&from).unwrap();
-        let sym_to = cfg.get_symbol(&to).unwrap();
 
-        result_graph.add_edge(&sym_from.name, &sym_to.name, ());
+    let result = analysis::cycle_report(cfg, kinds);
+    let mut groups: Vec<_> = result.symbols.iter().filter_map(|symbol| symbol.group.as_ref()).collect();
+    groups.sort();
+    groups.dedup();
+    eprintln!("Cycles: {}", groups.len());
+    result
+}
+
+fn main() -> Result<()> {
+    env_logger::init();
+    let args = Args::parse();
+
+    if let Some(Command::Serve { socket }) = &args.command {
+        let io = server::io_handler(Path::new(&args.index))?;
+        return match socket {
+            #[cfg(unix)]
+            Some(socket) => server::serve_socket(io, Path::new(socket)),
+            #[cfg(not(unix))]
+            Some(_) => bail!("Sockets are only supported on Unix"),
+            None => server::serve(&io, std::io::stdin().lock(), std::io::stdout().lock()),
+        };
+    }
+
+    if args.command.is_none() && args.query.is_none() && args.output.is_some() {
+        bail!("--output needs a query, the results of the shell are written with :save");
+    }
+
+    eprintln!(
+        "{} {}Loading index...",
+        style("[1/4]").bold().dim(),
+        LOOKING_GLASS
+    );
+    if let Some(Command::Export { output }) = &args.command {
+        return SymbolMap::load(&args.index)?.save(output);
     }
+    let cfg = ControlFlowGraph::load(&args.index)?;
+
+    let mut result = match (args.command, args.query) {
+        (Some(Command::Path { from, to, all, max_length, kinds }), _) => {
+            run_path(&cfg, &from, &to, kinds, all, max_length)?
+        }
+        (Some(Command::Dead { entries, patterns, config, exported, strict }), _) => {
+            let entries = EntryPoints {
+                names: entries,
+                patterns,
+                exported,
+                strict,
+            };
+            run_dead(&cfg, entries, config)?
+        }
+        (Some(Command::Cycles { kinds }), _) => run_cycles(&cfg, kinds),
+        (Some(Command::Export { .. } | Command::Serve { .. }), _) => unreachable!(),
+        (None, Some(query)) => run_query(&cfg, &query)?,
+        (None, None) => return repl::Repl::new(&cfg, args.format, args.collapse, args.cluster).run(),
+    };
 
-    for loc in res_symbols {
-        let sym= cfg.get_symbol(&loc).unwrap();
-        result_graph.add_node(&sym.name);
+    if let Some(grouping) = &args.collapse {
+        result = result.collapse(&cfg, grouping);
     }
+    if let Some(grouping) = &args.cluster {
+        result.group(&cfg, grouping);
+    }
+
+    eprintln!("Symbols: {:#?}", result.symbols.len());
+    eprintln!("Edges: {:#?}", result.edges.len());
 
-    // println!("{:?}", Dot::with_config(&result_graph, &[Config::EdgeNoLabel]));
-    std::fs::write("res.gv", format!("{:?}", Dot::with_config(&result_graph, &[Config::EdgeNoLabel]))).expect("Unable to write file");
+    eprintln!(
+        "{} {}Writing result...",
+        style("[4/4]").bold().dim(),
+        PAPER
+    );
+
+    match args.output {
+        Some(path) => {
+            let mut file = BufWriter::new(File::create(&path)?);
+            result.write(args.format, &mut file)?;
+            file.flush()?;
+        }
+        None => {
+            let stdout = std::io::stdout();
+            result.write(args.format, &mut stdout.lock())?;
+        }
+    }
     Ok(())
 }
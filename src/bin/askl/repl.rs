@@ -0,0 +1,186 @@
+use anyhow::Result;
+use askl::cfg::ControlFlowGraph;
+use askl::group::Grouping;
+use askl::output::Format;
+use askl::shell::{is_complete, Names};
+use askl::{Askl, QueryResult};
+use rustyline::completion::Completer;
+use rustyline::error::ReadlineError;
+use rustyline::validate::{ValidationContext, ValidationResult, Validator};
+use rustyline::{Context, Editor};
+use rustyline_derive::{Helper, Highlighter, Hinter};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+
+const HELP: &str = "\
+Queries span several lines until all their braces are closed.
+
+Commands:
+  :format [FORMAT]  Show or set the output format (dot, json, graphml, mermaid, csv, tree)
+  :save FILE        Write the result of the last query to FILE
+  :stats            Show the size of the index and of the last result
+  :help             Show this message
+  :quit             Leave the shell";
+
+/// Line editor support: completes symbol names and keeps reading lines while
+/// a query has unclosed braces
+#[derive(Helper, Highlighter, Hinter)]
+struct QueryHelper {
+    names: Names,
+}
+
+impl Completer for QueryHelper {
+    type Candidate = String;
+
+    fn complete(&self, line: &str, pos: usize, _ctx: &Context<'_>) -> rustyline::Result<(usize, Vec<String>)> {
+        Ok(self.names.complete(line, pos))
+    }
+}
+
+impl Validator for QueryHelper {
+    fn validate(&self, ctx: &mut ValidationContext) -> rustyline::Result<ValidationResult> {
+        let input = ctx.input();
+        Ok(if input.trim_start().starts_with(':') || is_complete(input) {
+            ValidationResult::Valid(None)
+        } else {
+            ValidationResult::Incomplete
+        })
+    }
+}
+
+/// Interactive shell running queries against an index loaded once
+pub struct Repl<'a> {
+    cfg: &'a ControlFlowGraph,
+    format: Format,
+    /// Grouping the results are collapsed by, as with --collapse
+    collapse: Option<Grouping>,
+    /// Grouping the results are clustered by, as with --cluster
+    cluster: Option<Grouping>,
+    last: Option<QueryResult>,
+}
+
+impl<'a> Repl<'a> {
+    pub fn new(
+        cfg: &'a ControlFlowGraph,
+        format: Format,
+        collapse: Option<Grouping>,
+        cluster: Option<Grouping>,
+    ) -> Self {
+        Self {
+            cfg,
+            format,
+            collapse,
+            cluster,
+            last: None,
+        }
+    }
+
+    fn history_path() -> Option<PathBuf> {
+        std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".askl_history"))
+    }
+
+    pub fn run(&mut self) -> Result<()> {
+        let names = Names::new(
+            self.cfg
+                .symbol_ids()
+                .filter_map(|id| self.cfg.symbol_name(id))
+                .flat_map(|(name, scope)| {
+                    let qualified = scope.map(|scope| format!("{}::{}", scope, name));
+                    std::iter::once(name.to_string()).chain(qualified)
+                })
+                .collect(),
+        );
+
+        let mut editor = Editor::<QueryHelper>::new()?;
+        editor.set_helper(Some(QueryHelper { names }));
+        let history = Self::history_path();
+        if let Some(history) = &history {
+            // There is no history before the first session
+            let _ = editor.load_history(history);
+        }
+
+        eprintln!("Type :help for help");
+        loop {
+            let line = match editor.readline("askl> ") {
+                Ok(line) => line,
+                Err(ReadlineError::Interrupted) => continue,
+                Err(ReadlineError::Eof) => break,
+                Err(err) => return Err(err.into()),
+            };
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            editor.add_history_entry(line);
+
+            let outcome = match line.strip_prefix(':') {
+                Some(command) => match self.command(command) {
+                    Ok(true) => continue,
+                    Ok(false) => break,
+                    Err(err) => Err(err),
+                },
+                None => self.query(line),
+            };
+            if let Err(err) = outcome {
+                eprintln!("{}", err);
+            }
+        }
+
+        if let Some(history) = &history {
+            editor.save_history(history)?;
+        }
+        Ok(())
+    }
+
+    fn query(&mut self, query: &str) -> Result<()> {
+        let mut result = Askl::new(query)?.run(self.cfg);
+        if let Some(grouping) = &self.collapse {
+            result = result.collapse(self.cfg, grouping);
+        }
+        if let Some(grouping) = &self.cluster {
+            result.group(self.cfg, grouping);
+        }
+        let stdout = std::io::stdout();
+        let mut stdout = stdout.lock();
+        result.write(self.format, &mut stdout)?;
+        writeln!(stdout)?;
+        self.last = Some(result);
+        Ok(())
+    }
+
+    /// Run a meta-command, returning whether the shell should go on
+    fn command(&mut self, command: &str) -> Result<bool> {
+        let mut words = command.split_whitespace();
+        match (words.next(), words.next()) {
+            (Some("quit" | "q"), None) => return Ok(false),
+            (Some("help" | "h"), None) => println!("{}", HELP),
+            (Some("format"), None) => println!("{}", self.format.name()),
+            (Some("format"), Some(format)) => self.format = format.parse()?,
+            (Some("save"), Some(path)) => match &self.last {
+                Some(result) => {
+                    let mut file = BufWriter::new(File::create(path)?);
+                    result.write(self.format, &mut file)?;
+                    file.flush()?;
+                }
+                None => anyhow::bail!("No query has been run yet"),
+            },
+            (Some("stats"), None) => {
+                if let Some(header) = self.cfg.header() {
+                    println!("Generated by: {}", header.generator);
+                    if let Some(revision) = &header.vcs_revision {
+                        println!("Revision: {}", revision);
+                    }
+                }
+                println!("Defined symbols: {}", self.cfg.symbol_ids().count());
+                println!("Nodes: {}", self.cfg.node_count());
+                println!("Edges: {}", self.cfg.edge_count());
+                if let Some(result) = &self.last {
+                    println!("Last result: {} symbols, {} edges", result.symbols.len(), result.edges.len());
+                }
+            }
+            _ => anyhow::bail!("Unknown command: :{}, see :help", command),
+        }
+        Ok(true)
+    }
+}
@@ -1,9 +1,19 @@
-use std::{fs::File, sync::Arc};
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    fs::File,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
 
 use anyhow::anyhow;
-use askl::symbols::{Symbol, SymbolId, SymbolMap, Symbols};
+use askl::symbols::{
+    Class, EdgeKind, FileHash, IndexHeader, Linkage, Reference, SourceRange, Symbol, SymbolId, SymbolMap, Symbols,
+    SCHEMA_VERSION,
+};
 use clap::Parser;
 use indicatif::ProgressBar;
+use log::{debug, warn};
 use serde::{Deserialize, Serialize};
 use tokio::{process::Command, sync::Semaphore};
 
@@ -26,6 +36,19 @@ struct Args {
     // Limit how many files can be processed
     #[clap(long)]
     trim: Option<usize>,
+
+    /// Run clang on every file, ignoring the symbols cached by previous runs
+    #[clap(long)]
+    no_cache: bool,
+
+    /// Path to write the index to, as JSON if it ends in .json
+    #[clap(short, long, default_value = "symbol_map.idx")]
+    output: PathBuf,
+
+    /// Root directory of the project, the directory of the compile commands
+    /// by default
+    #[clap(long)]
+    project_root: Option<PathBuf>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -44,13 +67,57 @@ pub enum Clang {
     // EnumConstantDecl(EnumConstantDecl),
     // EnumDecl(EnumDecl),
     FunctionDecl(FunctionDecl),
-    // NamespaceDecl(NamespaceDecl),
+    CXXMethodDecl(FunctionDecl),
+    CXXConstructorDecl(FunctionDecl),
+    CXXDestructorDecl(FunctionDecl),
+    CXXConversionDecl(FunctionDecl),
+    FunctionTemplateDecl,
+    NamespaceDecl(NamespaceDecl),
+    RecordDecl(RecordDecl),
+    CXXRecordDecl(RecordDecl),
+    ClassTemplateSpecializationDecl(RecordDecl),
+    FieldDecl(FieldDecl),
     DeclRefExpr(DeclRefExpr),
+    MemberExpr(MemberExpr),
+    CallExpr,
+    CXXMemberCallExpr,
+    CXXOperatorCallExpr,
+    CXXConstructExpr(ConstructExpr),
+    CXXTemporaryObjectExpr(ConstructExpr),
+    ImplicitCastExpr(Expr),
+    ParenExpr(Expr),
+    CXXThisExpr(Expr),
+    InitListExpr(InitListExpr),
+    BinaryOperator(BinaryOperator),
     TranslationUnitDecl,
     CompoundStmt,
     Other,
 }
 
+impl Clang {
+    fn is_function(&self) -> bool {
+        matches!(
+            self,
+            Clang::FunctionDecl(_)
+                | Clang::CXXMethodDecl(_)
+                | Clang::CXXConstructorDecl(_)
+                | Clang::CXXDestructorDecl(_)
+                | Clang::CXXConversionDecl(_)
+        )
+    }
+
+    fn function(&self) -> Option<&FunctionDecl> {
+        match self {
+            Clang::FunctionDecl(f)
+            | Clang::CXXMethodDecl(f)
+            | Clang::CXXConstructorDecl(f)
+            | Clang::CXXDestructorDecl(f)
+            | Clang::CXXConversionDecl(f) => Some(f),
+            _ => None,
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct EnumConstantDecl {
     pub name: String,
@@ -62,8 +129,30 @@ pub struct EnumDecl {
 }
 
 #[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct QualType {
+    pub qual_type: String,
+    pub desugared_qual_type: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
 pub struct FunctionDecl {
     pub name: Option<String>,
+    pub mangled_name: Option<String>,
+    pub storage_class: Option<String>,
+    pub previous_decl: Option<clang_ast::Id>,
+    /// Class of a method defined outside of it
+    pub parent_decl_context_id: Option<clang_ast::Id>,
+    #[serde(rename = "type")]
+    pub ty: Option<QualType>,
+    #[serde(default)]
+    pub is_implicit: bool,
+    /// Declared `virtual`, which methods overriding others need not be
+    #[serde(default, rename = "virtual")]
+    pub is_virtual: bool,
+    /// Virtual methods of base classes this method overrides
+    pub overrides: Option<Vec<Node>>,
     pub loc: Option<clang_ast::SourceLocation>,
     pub range: Option<clang_ast::SourceRange>,
 }
@@ -74,7 +163,48 @@ pub struct DeclRefExpr {
     pub name: Option<String>,
     pub loc: Option<clang_ast::SourceLocation>,
     pub range: Option<clang_ast::SourceRange>,
+    #[serde(rename = "type")]
+    pub ty: Option<QualType>,
     pub referenced_decl: Option<Box<Node>>,
+    /// How the reference is used, filled in by `node_simplify`
+    #[serde(skip)]
+    pub usage: Option<EdgeKind>,
+}
+
+/// Access to a member, such as the method in `object.method()`
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct MemberExpr {
+    pub name: Option<String>,
+    pub range: Option<clang_ast::SourceRange>,
+    #[serde(rename = "type")]
+    pub ty: Option<QualType>,
+    pub referenced_member_decl: Option<clang_ast::Id>,
+    /// How the member is used, filled in by `node_simplify`
+    #[serde(skip)]
+    pub usage: Option<EdgeKind>,
+    /// Class of the object the member is accessed on, filled in by
+    /// `node_simplify` where the type of the object is known
+    #[serde(skip)]
+    pub object: Option<String>,
+}
+
+/// Expression which only matters for its type, such as `this`
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Expr {
+    #[serde(rename = "type")]
+    pub ty: Option<QualType>,
+}
+
+/// Constructor call, which Clang does not link to the constructor
+/// declaration, so it is looked up by the class and the constructor type
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ConstructExpr {
+    pub range: Option<clang_ast::SourceRange>,
+    #[serde(rename = "type")]
+    pub ty: Option<QualType>,
+    pub ctor_type: Option<QualType>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -82,45 +212,663 @@ pub struct NamespaceDecl {
     pub name: Option<String>,
 }
 
-fn node_simplify(root: Node) -> Vec<Node> {
-    let inner: Vec<Node> = root
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct RecordDecl {
+    pub name: Option<String>,
+    #[serde(default)]
+    pub complete_definition: bool,
+    pub bases: Option<Vec<BaseSpecifier>>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct BaseSpecifier {
+    #[serde(rename = "type")]
+    pub ty: QualType,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct FieldDecl {
+    pub name: Option<String>,
+}
+
+/// Initializer of an aggregate, with one initializer per field in the order
+/// of the fields
+#[derive(Serialize, Deserialize, Debug)]
+pub struct InitListExpr {
+    #[serde(rename = "type")]
+    pub ty: Option<QualType>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct BinaryOperator {
+    pub opcode: String,
+}
+
+/// Keep only the nodes needed to build symbols, classifying each function
+/// reference by `usage`, the way the enclosing expression uses it
+///
+/// A call expression is dropped, but the references in its callee become
+/// calls and the references in its arguments become callback arguments.
+/// Namespaces, classes and templates are dropped as well, so that all
+/// functions end up directly in the translation unit, and function-like
+/// declarations, such as methods, become plain function declarations.
+fn node_simplify(root: Node, usage: EdgeKind) -> Vec<Node> {
+    let child_usage = match root.kind {
+        Clang::CallExpr | Clang::CXXMemberCallExpr | Clang::CXXOperatorCallExpr => {
+            let mut children = root.inner.into_iter();
+            let callee = children.next();
+            return callee
+                .into_iter()
+                .flat_map(|node| node_simplify(node, EdgeKind::Call))
+                .chain(children.flat_map(|node| node_simplify(node, EdgeKind::CallbackArgument)))
+                .collect();
+        }
+        Clang::CXXConstructExpr(_) | Clang::CXXTemporaryObjectExpr(_) => EdgeKind::CallbackArgument,
+        _ => usage,
+    };
+    // The object is simplified away along with its type
+    let object = match root.kind {
+        Clang::MemberExpr(_) => root.inner.first().and_then(object_class).map(str::to_string),
+        _ => None,
+    };
+    let mut inner: Vec<Node> = root
         .inner
         .into_iter()
-        .map(|node| node_simplify(node))
-        .flatten()
+        .flat_map(|node| node_simplify(node, child_usage))
         .collect();
-    match &root.kind {
-        Clang::DeclRefExpr(ref_expr) => {
-            if let Some(referenced_decl) = &ref_expr.referenced_decl {
-                if let Clang::FunctionDecl(_) = &referenced_decl.kind {
-                    return vec![Node {
-                        id: root.id,
-                        kind: root.kind,
-                        inner: inner,
-                    }];
-                }
+    let node = |kind, inner| Node {
+        id: root.id,
+        kind,
+        inner,
+    };
+
+    match root.kind {
+        Clang::DeclRefExpr(mut ref_expr) => {
+            if ref_expr
+                .referenced_decl
+                .as_ref()
+                .is_some_and(|decl| decl.kind.is_function())
+            {
+                ref_expr.usage = Some(usage);
+                return vec![node(Clang::DeclRefExpr(ref_expr), inner)];
             }
             vec![]
         }
-        Clang::FunctionDecl(_) => {
-            vec![Node {
-                id: root.id,
-                kind: root.kind,
-                inner: inner,
-            }]
+        Clang::MemberExpr(mut member) => {
+            member.usage = Some(usage);
+            member.object = object;
+            inner.insert(0, node(Clang::MemberExpr(member), vec![]));
+            inner
+        }
+        kind @ (Clang::CXXConstructExpr(_) | Clang::CXXTemporaryObjectExpr(_)) => {
+            inner.insert(0, node(kind, vec![]));
+            inner
+        }
+        Clang::FunctionDecl(f)
+        | Clang::CXXMethodDecl(f)
+        | Clang::CXXConstructorDecl(f)
+        | Clang::CXXDestructorDecl(f)
+        | Clang::CXXConversionDecl(f) => {
+            // Functions declared inside, such as methods of local classes,
+            // become functions of their own
+            let (nested, inner): (Vec<Node>, Vec<Node>) = inner
+                .into_iter()
+                .partition(|node| matches!(node.kind, Clang::FunctionDecl(_)));
+            let mut nodes = vec![node(Clang::FunctionDecl(f), inner)];
+            nodes.extend(nested);
+            nodes
+        }
+        Clang::TranslationUnitDecl => vec![node(Clang::TranslationUnitDecl, inner)],
+        Clang::Other
+        | Clang::CompoundStmt
+        | Clang::CallExpr
+        | Clang::CXXMemberCallExpr
+        | Clang::CXXOperatorCallExpr
+        | Clang::FunctionTemplateDecl
+        | Clang::NamespaceDecl(_)
+        | Clang::RecordDecl(_)
+        | Clang::CXXRecordDecl(_)
+        | Clang::ClassTemplateSpecializationDecl(_)
+        | Clang::FieldDecl(_)
+        | Clang::InitListExpr(_)
+        | Clang::BinaryOperator(_)
+        | Clang::ImplicitCastExpr(_)
+        | Clang::ParenExpr(_)
+        | Clang::CXXThisExpr(_) => inner,
+    }
+}
+
+/// Class of the object an expression like `object` or `pointer` in
+/// `object.method()` and `pointer->method()` evaluates to
+fn object_class(node: &Node) -> Option<&str> {
+    let ty = match &node.kind {
+        Clang::DeclRefExpr(r) => r.ty.as_ref(),
+        Clang::MemberExpr(m) => m.ty.as_ref(),
+        Clang::ImplicitCastExpr(e) | Clang::ParenExpr(e) | Clang::CXXThisExpr(e) => e.ty.as_ref(),
+        _ => None,
+    }?;
+    Some(class_name(ty).trim_end_matches(['*', '&', ' ']))
+}
+
+/// Declaration of a function, as needed to compute its identity
+struct FunctionInfo {
+    name: String,
+    /// Qualified name of the enclosing namespaces and classes
+    scope: Option<String>,
+    mangled_name: Option<String>,
+    /// Declared `static` or inside an anonymous namespace
+    is_internal: bool,
+    file: Option<Arc<str>>,
+    previous_decl: Option<clang_ast::Id>,
+    /// Template pattern this function is an instantiation of
+    template: Option<clang_ast::Id>,
+    /// Has a body
+    is_definition: bool,
+    /// Declared `virtual` or overriding a virtual method
+    is_virtual: bool,
+}
+
+/// Where a declaration is nested while walking the AST
+#[derive(Clone, Default)]
+struct DeclContext {
+    scope: Option<String>,
+    is_internal: bool,
+    template: Option<clang_ast::Id>,
+    /// Name of the enclosing struct, which names its fields
+    record: Option<String>,
+}
+
+impl DeclContext {
+    fn nested(&self, name: &str, is_internal: bool) -> Self {
+        let scope = match &self.scope {
+            Some(scope) => format!("{}::{}", scope, name),
+            None => name.to_string(),
+        };
+        Self {
+            scope: Some(scope),
+            is_internal: self.is_internal || is_internal,
+            template: None,
+            record: None,
+        }
+    }
+}
+
+/// Strip qualifiers and tags from the type of a constructed object
+fn class_name(ty: &QualType) -> &str {
+    let mut name = ty.desugared_qual_type.as_deref().unwrap_or(&ty.qual_type).trim();
+    for prefix in ["const ", "volatile ", "struct ", "class ", "union "] {
+        name = name.strip_prefix(prefix).unwrap_or(name);
+    }
+    name
+}
+
+/// Identities of all functions declared in a translation unit
+///
+/// Clang refers to declarations by ids which are only meaningful within one
+/// translation unit, and a function may be declared several times. All
+/// declarations of a function resolve to the identity of its first
+/// declaration, and all instantiations of a function template to the identity
+/// of the template.
+#[derive(Default)]
+struct FunctionIds {
+    functions: HashMap<clang_ast::Id, FunctionInfo>,
+    /// Namespaces and classes by id, for methods defined out of their class
+    scopes: HashMap<clang_ast::Id, DeclContext>,
+    /// Explicit constructors by qualified class name and constructor type
+    constructors: HashMap<(String, String), clang_ast::Id>,
+    /// Bases of the defined classes by qualified class name
+    classes: HashMap<String, Class>,
+    /// Names of struct fields, such as `file_ops.open`
+    fields: HashMap<clang_ast::Id, String>,
+    /// Fields of each struct by its name, in the order of declaration
+    records: HashMap<String, Vec<clang_ast::Id>>,
+    /// Functions stored into each struct field
+    stores: HashMap<String, Vec<SymbolId>>,
+    /// Directory of the compile command, which file names are relative to
+    directory: PathBuf,
+    /// Contents of the source files by name, read when first needed
+    sources: RefCell<HashMap<Arc<str>, Option<Vec<u8>>>>,
+}
+
+impl FunctionIds {
+    fn new(root: &Node, directory: &Path) -> Self {
+        let mut functions = FunctionIds {
+            directory: directory.to_path_buf(),
+            ..Default::default()
+        };
+        functions.collect(root, &DeclContext::default());
+        // Stores can only be resolved once all declarations are known
+        functions.collect_stores(root);
+        functions
+    }
+
+    fn collect(&mut self, node: &Node, context: &DeclContext) {
+        let context = match &node.kind {
+            Clang::NamespaceDecl(ns) => {
+                let name = ns.name.as_deref().unwrap_or("(anonymous namespace)");
+                let nested = context.nested(name, ns.name.is_none());
+                self.scopes.insert(node.id, nested.clone());
+                nested
+            }
+            Clang::RecordDecl(record) => {
+                // C structs do not nest names
+                DeclContext {
+                    record: record.name.clone(),
+                    ..context.clone()
+                }
+            }
+            Clang::CXXRecordDecl(record) | Clang::ClassTemplateSpecializationDecl(record) => {
+                let mut nested = context.nested(record.name.as_deref().unwrap_or("(anonymous)"), false);
+                if record.name.is_some() {
+                    nested.record = nested.scope.clone();
+                }
+                self.scopes.insert(node.id, nested.clone());
+                if let (Some(name), true) = (&nested.scope, record.complete_definition) {
+                    let bases = record.bases.iter().flatten();
+                    let bases = bases.map(|base| class_name(&base.ty).to_string()).collect();
+                    self.classes.insert(name.clone(), Class { bases });
+                }
+                nested
+            }
+            Clang::FunctionTemplateDecl => {
+                // The first function is the pattern, the others are its
+                // instantiations
+                let pattern = node.inner.iter().find(|inner| inner.kind.is_function());
+                DeclContext {
+                    template: pattern.map(|pattern| pattern.id),
+                    ..context.clone()
+                }
+            }
+            Clang::FieldDecl(field) => {
+                if let (Some(record), Some(name)) = (&context.record, &field.name) {
+                    self.fields.insert(node.id, format!("{}.{}", record, name));
+                    self.records.entry(record.clone()).or_default().push(node.id);
+                }
+                context.clone()
+            }
+            kind => {
+                if let Some(f) = kind.function() {
+                    self.collect_function(node, f, context);
+                }
+                context.clone()
+            }
+        };
+
+        for inner in node.inner.iter() {
+            self.collect(inner, &context);
+        }
+    }
+
+    fn collect_function(&mut self, node: &Node, f: &FunctionDecl, context: &DeclContext) {
+        let id = node.id;
+        let name = if let Some(name) = &f.name {
+            name
+        } else {
+            return;
+        };
+        let parent = f
+            .parent_decl_context_id
+            .and_then(|parent| self.scopes.get(&parent))
+            .unwrap_or(context);
+        let file = f.loc.as_ref().and_then(|loc| {
+            loc.expansion_loc
+                .as_ref()
+                .or(loc.spelling_loc.as_ref())
+                .map(|loc| loc.file.clone())
+        });
+
+        if let (Clang::CXXConstructorDecl(_), Some(scope), Some(ty), false) =
+            (&node.kind, &parent.scope, &f.ty, f.is_implicit)
+        {
+            self.constructors
+                .entry((scope.clone(), ty.qual_type.clone()))
+                .or_insert(id);
+        }
+
+        self.functions.insert(
+            id,
+            FunctionInfo {
+                name: name.clone(),
+                scope: parent.scope.clone(),
+                mangled_name: f.mangled_name.clone(),
+                is_internal: f.storage_class.as_deref() == Some("static") || parent.is_internal,
+                file,
+                previous_decl: f.previous_decl,
+                template: context.template.filter(|pattern| *pattern != id),
+                is_definition: node.inner.iter().any(|inner| matches!(inner.kind, Clang::CompoundStmt)),
+                is_virtual: f.is_virtual || f.overrides.as_ref().is_some_and(|overrides| !overrides.is_empty()),
+            },
+        );
+    }
+
+    /// Record the functions stored into struct fields by initializers, such
+    /// as `{ .open = file_open }`, and by assignments, such as
+    /// `ops->open = file_open`
+    fn collect_stores(&mut self, node: &Node) {
+        match &node.kind {
+            Clang::InitListExpr(InitListExpr { ty: Some(ty) }) => {
+                let fields = self.records.get(class_name(ty)).cloned().unwrap_or_default();
+                for (field, init) in fields.iter().zip(node.inner.iter()) {
+                    self.store(*field, init);
+                }
+            }
+            Clang::BinaryOperator(op) if op.opcode == "=" => {
+                if let [target, value] = &node.inner[..] {
+                    if let Some(field) = Self::member(target) {
+                        self.store(field, value);
+                    }
+                }
+            }
+            _ => {}
+        }
+
+        for inner in node.inner.iter() {
+            self.collect_stores(inner);
+        }
+    }
+
+    fn store(&mut self, field: clang_ast::Id, value: &Node) {
+        let function = Self::function_value(value).and_then(|id| self.resolve(id));
+        if let (Some(field), Some((function, _, _))) = (self.fields.get(&field), function) {
+            self.stores.entry(field.clone()).or_default().push(function);
+        }
+    }
+
+    /// Declaration of the member accessed by an expression, looking through
+    /// casts and parentheses
+    fn member(node: &Node) -> Option<clang_ast::Id> {
+        match (&node.kind, &node.inner[..]) {
+            (Clang::MemberExpr(m), _) => m.referenced_member_decl,
+            (Clang::Other | Clang::ImplicitCastExpr(_) | Clang::ParenExpr(_), [inner]) => Self::member(inner),
+            _ => None,
         }
-        Clang::TranslationUnitDecl => {
-            vec![Node {
-                id: root.id,
-                kind: root.kind,
-                inner: inner,
-            }]
+    }
+
+    /// Function an expression evaluates to, looking through casts,
+    /// parentheses and `&`
+    fn function_value(node: &Node) -> Option<clang_ast::Id> {
+        match (&node.kind, &node.inner[..]) {
+            (Clang::DeclRefExpr(r), _) => r
+                .referenced_decl
+                .as_ref()
+                .filter(|decl| decl.kind.is_function())
+                .map(|decl| decl.id),
+            (Clang::Other | Clang::ImplicitCastExpr(_) | Clang::ParenExpr(_), [inner]) => Self::function_value(inner),
+            _ => None,
+        }
+    }
+
+    /// Whether the declaration `id` has a body
+    fn is_definition(&self, id: clang_ast::Id) -> bool {
+        self.functions.get(&id).is_some_and(|f| f.is_definition)
+    }
+
+    fn resolve(&self, id: clang_ast::Id) -> Option<(SymbolId, Linkage, &FunctionInfo)> {
+        let mut first = self.functions.get(&id)?;
+        if let Some(pattern) = first.template.and_then(|pattern| self.functions.get(&pattern)) {
+            first = pattern;
+        }
+        let mut is_internal = first.is_internal;
+        // Redeclaration chains are acyclic, the bound only protects against
+        // malformed input
+        for _ in 0..self.functions.len() {
+            match first.previous_decl.and_then(|prev| self.functions.get(&prev)) {
+                Some(prev) => {
+                    first = prev;
+                    is_internal |= prev.is_internal;
+                }
+                None => break,
+            }
+        }
+
+        let key = match (&first.mangled_name, &first.scope) {
+            (Some(mangled_name), _) => mangled_name.clone(),
+            (None, Some(scope)) => format!("{}::{}", scope, first.name),
+            (None, None) => first.name.clone(),
+        };
+        if is_internal {
+            let file = first.file.as_deref().unwrap_or("");
+            Some((SymbolId::new(format!("{}@{}", key, file)), Linkage::Internal, first))
+        } else {
+            Some((SymbolId::new(key), Linkage::External, first))
+        }
+    }
+
+    /// Call through a function pointer in a struct field, which is resolved
+    /// once the stores of all translation units are known
+    fn field_call(&self, node: &Node) -> Option<Reference> {
+        match &node.kind {
+            Clang::MemberExpr(m) if m.usage == Some(EdgeKind::Call) => {
+                let field = self.fields.get(&m.referenced_member_decl?)?;
+                Some(Reference {
+                    kind: EdgeKind::Indirect,
+                    range: m.range.as_ref().and_then(SourceRange::from_clang),
+                    via: Some(field.clone()),
+                })
+            }
+            _ => None,
+        }
+    }
+
+    /// Whether the member is named with its class, as in
+    /// `object.Base::method()`, which the AST leaves to the source to tell
+    fn is_qualified(&self, member: &MemberExpr) -> bool {
+        let name = match member.range.as_ref().and_then(|range| range.end.spelling_loc.as_ref()) {
+            Some(name) => name,
+            None => return false,
+        };
+        let mut sources = self.sources.borrow_mut();
+        let source = sources
+            .entry(name.file.clone())
+            .or_insert_with(|| std::fs::read(self.directory.join(&*name.file)).ok());
+        source
+            .as_ref()
+            .and_then(|source| source.get(..name.offset))
+            .is_some_and(|before| before.trim_ascii_end().ends_with(b"::"))
+    }
+
+    /// The constructor called to create an object of type `ty`
+    fn resolve_constructor(&self, construct: &ConstructExpr) -> Option<SymbolId> {
+        let class = class_name(construct.ty.as_ref()?);
+        let ctor_type = &construct.ctor_type.as_ref()?.qual_type;
+        let id = match self.constructors.get(&(class.to_string(), ctor_type.clone())) {
+            Some(id) => *id,
+            None => {
+                // The type may be spelled without all of its scopes
+                let suffix = format!("::{}", class);
+                let mut candidates = self
+                    .constructors
+                    .iter()
+                    .filter(|((scope, ty), _)| scope.ends_with(&suffix) && ty == ctor_type);
+                match (candidates.next(), candidates.next()) {
+                    (Some((_, id)), None) => *id,
+                    _ => return None,
+                }
+            }
+        };
+        self.resolve(id).map(|(id, _, _)| id)
+    }
+
+    /// The function a simplified reference node refers to, with the way it
+    /// is used and where
+    fn reference(&self, node: &Node) -> Option<(SymbolId, Reference)> {
+        let (child, kind, range, via) = match &node.kind {
+            Clang::DeclRefExpr(r) => {
+                let ref_decl = r.referenced_decl.as_ref()?;
+                let f = ref_decl.kind.function()?;
+                let child = match self.resolve(ref_decl.id) {
+                    Some((child, _, _)) => child,
+                    None => SymbolId::new(f.name.clone()?),
+                };
+                (child, r.usage, &r.range, None)
+            }
+            Clang::MemberExpr(m) => {
+                let (child, _, f) = self.resolve(m.referenced_member_decl?)?;
+                // Calls dispatched at run time are marked by the class of
+                // their object, unless the class is named, as in
+                // `object.Base::method()`, which calls that very method
+                let via = (m.usage == Some(EdgeKind::Call) && f.is_virtual && !self.is_qualified(m))
+                    .then(|| m.object.clone().or_else(|| f.scope.clone()))
+                    .flatten();
+                (child, m.usage, &m.range, via)
+            }
+            Clang::CXXConstructExpr(c) | Clang::CXXTemporaryObjectExpr(c) => {
+                (self.resolve_constructor(c)?, Some(EdgeKind::Call), &c.range, None)
+            }
+            _ => return None,
+        };
+        Some((
+            child,
+            Reference {
+                kind: kind.unwrap_or(EdgeKind::AddressTaken),
+                range: range.as_ref().and_then(SourceRange::from_clang),
+                via,
+            },
+        ))
+    }
+}
+
+/// Symbols defined or referenced in a single translation unit
+fn symbols_from_tu(root: Node, functions: &FunctionIds) -> SymbolMap {
+    let mut symbol_map = SymbolMap::new();
+    for node in root.inner {
+        if let Clang::FunctionDecl(f) = node.kind {
+            if f.is_implicit {
+                continue;
+            }
+            let (id, linkage, info) = if let Some(resolved) = functions.resolve(node.id) {
+                resolved
+            } else {
+                continue;
+            };
+
+            let mut children: HashMap<SymbolId, Vec<Reference>> = HashMap::new();
+            for (child, reference) in node.inner.iter().filter_map(|i| functions.reference(i)) {
+                children.entry(child).or_default().push(reference);
+            }
+            let overrides = f
+                .overrides
+                .iter()
+                .flatten()
+                .filter_map(|method| functions.resolve(method.id))
+                .map(|(id, _, _)| id)
+                .collect();
+            let field_calls = node.inner.iter().filter_map(|i| functions.field_call(i)).collect();
+            let range = f.range.as_ref().and_then(SourceRange::from_clang);
+            let definition = range.clone().filter(|_| functions.is_definition(node.id));
+
+            symbol_map.add(
+                id,
+                Symbol {
+                    name: info.name.clone(),
+                    scope: info.scope.clone(),
+                    linkage,
+                    ranges: range.into_iter().collect(),
+                    definition,
+                    children,
+                    overrides,
+                    field_calls,
+                },
+            );
+        }
+    }
+    for (name, class) in functions.classes.iter() {
+        symbol_map.add_class(name.clone(), class.clone());
+    }
+    for (field, stored) in functions.stores.iter() {
+        symbol_map.add_field_stores(field.clone(), stored.clone());
+    }
+    symbol_map
+}
+
+/// Symbols of a translation unit, stored next to its object file
+#[derive(Serialize, Deserialize)]
+struct CachedSymbols {
+    /// Hash of the inputs the symbols were generated from, see [`input_hash`]
+    hash: FileHash,
+    /// Files included by the source file, relative to the directory of the
+    /// compile command
+    dependencies: Vec<PathBuf>,
+    symbols: SymbolMap,
+}
+
+/// Hash of the version of gen-ast and of the index, of the compile command,
+/// and of the contents of the source file and the files it includes
+fn input_hash(args: &Args, arguments: &[String], directory: &Path, source: &str, dependencies: &[PathBuf]) -> std::io::Result<FileHash> {
+    let mut contents = vec![std::fs::read(directory.join(source))?];
+    for dependency in dependencies.iter() {
+        contents.push(std::fs::read(directory.join(dependency))?);
+    }
+    let schema_version = SCHEMA_VERSION.to_le_bytes();
+    let parts = [env!("CARGO_PKG_VERSION").as_bytes(), &schema_version, args.clang.as_bytes()]
+        .into_iter()
+        .chain(arguments.iter().map(|argument| argument.as_bytes()))
+        .chain(dependencies.iter().map(|dependency| dependency.as_os_str().as_encoded_bytes()))
+        .chain(contents.iter().map(Vec::as_slice));
+    Ok(FileHash::new(parts))
+}
+
+/// Cached symbols, if none of their inputs changed since they were stored
+fn load_cached(ast_file: &Path, hash: impl FnOnce(&[PathBuf]) -> std::io::Result<FileHash>) -> Option<SymbolMap> {
+    // Entries of older versions of gen-ast fail to parse or to match
+    let cached: CachedSymbols = serde_json::from_slice(&std::fs::read(ast_file).ok()?).ok()?;
+    // A dependency that is gone has changed
+    (cached.hash == hash(&cached.dependencies).ok()?).then_some(cached.symbols)
+}
+
+fn store_cached(ast_file: &Path, hash: FileHash, dependencies: Vec<PathBuf>, symbols: SymbolMap) -> SymbolMap {
+    let cached = CachedSymbols { hash, dependencies, symbols };
+    if let Err(err) = serde_json::to_vec(&cached)
+        .map_err(anyhow::Error::from)
+        .and_then(|json| Ok(std::fs::write(ast_file, json)?))
+    {
+        warn!("Failed to cache symbols in {:?}: {}", ast_file, err);
+    }
+    cached.symbols
+}
+
+/// Files listed by a dependency file clang writes for `-MD`, in the form
+/// `target: source header...`, where `\` continues lines and escapes spaces
+fn parse_dependencies(text: &str) -> Vec<PathBuf> {
+    let text = text.replace("\\\r\n", " ").replace("\\\n", " ");
+    let files = match text.split_once(": ") {
+        Some((_, files)) => files,
+        None => return vec![],
+    };
+
+    let mut dependencies = vec![];
+    let mut file = String::new();
+    let mut chars = files.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if chars.peek() == Some(&' ') => file.push(chars.next().unwrap()),
+            '$' if chars.peek() == Some(&'$') => file.push(chars.next().unwrap()),
+            c if c.is_whitespace() => {
+                if !file.is_empty() {
+                    dependencies.push(PathBuf::from(std::mem::take(&mut file)));
+                }
+            }
+            c => file.push(c),
         }
-        Clang::Other | Clang::CompoundStmt => inner,
     }
+    if !file.is_empty() {
+        dependencies.push(PathBuf::from(file));
+    }
+    dependencies
 }
 
-async fn run_ast_gen(args: Args, c: CompileCommand) -> anyhow::Result<(String, Node)> {
+/// Whether an argument controls the dependency files of the build, which
+/// gen-ast must not overwrite, and whether it is followed by a value
+fn dependency_option(argument: &str) -> Option<bool> {
+    match argument {
+        "-MF" | "-MT" | "-MQ" | "-MJ" => Some(true),
+        argument if argument.starts_with("-M") => Some(false),
+        _ => None,
+    }
+}
+
+async fn run_ast_gen(args: Args, c: CompileCommand) -> anyhow::Result<(PathBuf, SymbolMap)> {
     let mut arguments = if let Some(ref command) = c.command {
         shell_words::split(command).expect("Failed to parse command")
     } else if let Some(arguments) = c.arguments {
@@ -132,24 +880,44 @@ async fn run_ast_gen(args: Args, c: CompileCommand) -> anyhow::Result<(String, N
         ));
     };
 
-    let ast_file;
-    if let Some(i) = arguments.iter().position(|opt| *opt == "-o") {
-        // Replace option of type "-o outfile"
-        ast_file = format!("{}/{}.ast", c.directory, arguments[i + 1]);
-        // arguments[i + 1] = output;
-    } else if let Some(i) = arguments.iter().position(|opt| opt.starts_with("-o")) {
-        // Replace option of type "-ooutfile"
-        ast_file = format!("{}/{}.ast", c.directory, &arguments[i + 1][2..]);
-        // arguments[i] = format!("-o{}", output);
+    let directory = Path::new(&c.directory);
+    let output = if let Some(i) = arguments.iter().position(|opt| *opt == "-o") {
+        // Option of type "-o outfile"
+        arguments.get(i + 1).cloned()
     } else {
-        ast_file = format!("{}/{}.ast", c.directory, c.file);
-        // arguments.push(format!("-o{}", output));
+        // Option of type "-ooutfile"
+        arguments
+            .iter()
+            .find_map(|opt| opt.strip_prefix("-o").map(str::to_string))
+    };
+    let ast_file = directory.join(format!("{}.ast", output.as_ref().unwrap_or(&c.file)));
+    let dependency_file = directory.join(format!("{}.ast.d", output.as_ref().unwrap_or(&c.file)));
+
+    let compile_command = arguments.clone();
+    let hash = |dependencies: &[PathBuf]| input_hash(&args, &compile_command, directory, &c.file, dependencies);
+    if !args.no_cache {
+        if let Some(symbols) = load_cached(&ast_file, hash) {
+            debug!("Reusing symbols of {} from {:?}", c.file, ast_file);
+            return Ok((ast_file, symbols));
+        }
     }
 
+    let mut skip_value = false;
+    arguments.retain(|arg| match (std::mem::take(&mut skip_value), dependency_option(arg)) {
+        (true, _) => false,
+        (false, Some(has_value)) => {
+            skip_value = has_value;
+            false
+        }
+        (false, None) => true,
+    });
     arguments = vec![
         "-Xclang".to_string(),
         "-ast-dump=json".to_string(),
         "-fsyntax-only".to_string(),
+        "-MD".to_string(),
+        "-MF".to_string(),
+        dependency_file.to_string_lossy().into_owned(),
     ]
     .into_iter()
     .chain(
@@ -162,7 +930,7 @@ async fn run_ast_gen(args: Args, c: CompileCommand) -> anyhow::Result<(String, N
     .collect();
 
     let output = Command::new(args.clang.clone())
-        .current_dir(c.directory)
+        .current_dir(directory)
         .args(arguments)
         .output()
         .await?;
@@ -171,15 +939,35 @@ async fn run_ast_gen(args: Args, c: CompileCommand) -> anyhow::Result<(String, N
 
     let node: Node = serde_json::from_str(&json)?;
 
-    let simple_node = node_simplify(node).pop().unwrap();
+    let functions = FunctionIds::new(&node, directory);
+    let simple_node = node_simplify(node, EdgeKind::AddressTaken).pop().unwrap();
 
-    Ok((ast_file, simple_node))
+    let symbols = symbols_from_tu(simple_node, &functions);
+    let dependencies = match std::fs::read_to_string(&dependency_file) {
+        Ok(text) => {
+            let _ = std::fs::remove_file(&dependency_file);
+            parse_dependencies(&text)
+        }
+        Err(err) => {
+            warn!("Not caching symbols of {}, clang wrote no dependencies: {}", c.file, err);
+            return Ok((ast_file, symbols));
+        }
+    };
+    let hash = hash(&dependencies)?;
+    Ok((ast_file.clone(), store_cached(&ast_file, hash, dependencies, symbols)))
+}
+
+/// First line of `clang --version`
+async fn clang_version(clang: &str) -> Option<String> {
+    let output = Command::new(clang).arg("--version").output().await.ok()?;
+    let version = String::from_utf8(output.stdout).ok()?;
+    version.lines().next().map(str::to_string)
 }
 
 async fn parse_all(
     args: Args,
     compile_commands: Vec<CompileCommand>,
-) -> Vec<anyhow::Result<(String, Node)>> {
+) -> Vec<anyhow::Result<(PathBuf, SymbolMap)>> {
     let sem = Arc::new(Semaphore::new(args.parallelism));
     let mut tasks = Vec::with_capacity(compile_commands.len());
     let pb = ProgressBar::new(compile_commands.len() as u64);
@@ -198,7 +986,7 @@ async fn parse_all(
         }));
     }
 
-    let mut outputs = Vec::<anyhow::Result<(String, Node)>>::with_capacity(tasks.len());
+    let mut outputs = Vec::<anyhow::Result<(PathBuf, SymbolMap)>>::with_capacity(tasks.len());
     for task in tasks {
         outputs.push(task.await.unwrap());
     }
@@ -219,64 +1007,34 @@ async fn main() -> anyhow::Result<()> {
         compile_commands.truncate(trim);
     }
 
+    let compile_commands_path = Path::new(&args.compile_commands).canonicalize()?;
+    let project_root = match &args.project_root {
+        Some(root) => root.canonicalize()?,
+        None => compile_commands_path.parent().unwrap().to_path_buf(),
+    };
+    let mut header = IndexHeader::new("gen-ast", &project_root);
+    header.clang_version = clang_version(&args.clang).await;
+    header.compile_commands = Some(compile_commands_path);
+
+    let output = args.output.clone();
     let outputs = parse_all(args, compile_commands).await;
 
-    let all_ast = outputs
+    let mut symbol_map = SymbolMap::new();
+    symbol_map.header = Some(header);
+    for (_, tu_symbols) in outputs
         .into_iter()
-        .map(|r| {
-            if let Err(err) = &r {
+        .inspect(|r| {
+            if let Err(err) = r {
                 println!("{:?}", err);
             }
-            r
-        })
-        .filter(|r| r.is_ok())
-        .map(|r| r.unwrap())
-        .map(|(_, node)| node)
-        .reduce(|mut acc, node| {
-            acc.inner.extend(node.inner);
-            Node {
-                id: acc.id,
-                kind: acc.kind,
-                inner: acc.inner,
-            }
         })
-        .unwrap();
-
-    let mut symbol_map = SymbolMap::new();
-    for node in all_ast.inner {
-        if let Clang::FunctionDecl(f) = node.kind {
-            let children = node
-                .inner
-                .iter()
-                .filter_map(|i| {
-                    if let Clang::DeclRefExpr(r) = &i.kind {
-                        if let Some(ref_decl) = &r.referenced_decl {
-                            if let Clang::FunctionDecl(f) = &ref_decl.kind {
-                                if let Some(name) = &f.name {
-                                    return Some(SymbolId::new(name.clone()));
-                                }
-                            }
-                        }
-                    }
-                    None
-                })
-                .collect();
-
-            symbol_map.add(
-                SymbolId::new(f.name.clone().unwrap()),
-                Symbol {
-                    name: f.name.clone().unwrap(),
-                    ranges: vec![f.range.unwrap()],
-                    children: children,
-                },
-            );
-        }
+        .flatten()
+    {
+        symbol_map.merge(tu_symbols);
     }
+    // Overrides and stores may be in other translation units than the calls
+    symbol_map.resolve_field_calls();
+    symbol_map.resolve_virtual_calls();
 
-    std::fs::write(
-        "symbol_map.json",
-        serde_json::to_string_pretty(&symbol_map).unwrap(),
-    )
-    .unwrap();
-    Ok(())
+    symbol_map.save(&output)
 }
@@ -0,0 +1,118 @@
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+
+use anyhow::{anyhow, Result};
+use askl::lsp::{LspClient, LspIndexer};
+use askl::symbols::IndexHeader;
+use clap::Parser;
+use console::{style, Emoji};
+use indicatif::ProgressBar;
+use serde::Deserialize;
+use tokio::process::Command;
+
+/// Indexer for askl driving a language server, such as clangd
+#[derive(Parser, Debug)]
+#[clap(author, version, about, long_about = None)]
+struct Args {
+    /// Command starting the language server
+    #[clap(long)]
+    lsp_command: String,
+
+    /// Root directory of the project
+    #[clap(long)]
+    project_root: PathBuf,
+
+    /// Files listed here are opened, so that the server indexes them even
+    /// without a background index
+    #[clap(long)]
+    compile_commands: Option<PathBuf>,
+
+    /// Language identifier of the opened files
+    #[clap(long, default_value = "c")]
+    language: String,
+
+    /// Path to write the index to, in the binary format unless it ends in
+    /// .json
+    #[clap(short, long, default_value = "symbol_map.idx")]
+    output: PathBuf,
+}
+
+#[derive(Deserialize, Debug)]
+struct CompileCommand {
+    directory: PathBuf,
+    file: PathBuf,
+}
+
+static LOOKING_GLASS: Emoji<'_, '_> = Emoji("🔍  ", "");
+static CLIP: Emoji<'_, '_> = Emoji("🔗  ", "");
+static PAPER: Emoji<'_, '_> = Emoji("📃  ", "");
+
+fn source_files(compile_commands: &Path) -> Result<Vec<PathBuf>> {
+    let commands: Vec<CompileCommand> = serde_json::from_slice(&std::fs::read(compile_commands)?)?;
+    let mut files: Vec<PathBuf> = commands
+        .into_iter()
+        .map(|c| c.directory.join(c.file))
+        .collect();
+    files.sort();
+    files.dedup();
+    Ok(files)
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    env_logger::init();
+    let args = Args::parse();
+    let root = args.project_root.canonicalize()?;
+
+    println!(
+        "{} {}Starting language server...",
+        style("[1/3]").bold().dim(),
+        LOOKING_GLASS
+    );
+    let command = shell_words::split(&args.lsp_command)?;
+    let (program, arguments) = command
+        .split_first()
+        .ok_or_else(|| anyhow!("Empty language server command"))?;
+    let mut server = Command::new(program)
+        .args(arguments)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()?;
+    let mut client = LspClient::new(server.stdout.take().unwrap(), server.stdin.take().unwrap());
+    let server_info = client.initialize(&root).await?.server_info;
+    let mut header = IndexHeader::new("lsp-index", &root);
+    header.clang_version = server_info.map(|info| match info.version {
+        Some(version) => format!("{} {}", info.name, version),
+        None => info.name,
+    });
+    header.compile_commands = args.compile_commands.as_ref().and_then(|path| path.canonicalize().ok());
+
+    if let Some(compile_commands) = &args.compile_commands {
+        let files = source_files(compile_commands)?;
+        println!(
+            "{} {}Opening {} files...",
+            style("[2/3]").bold().dim(),
+            CLIP,
+            files.len()
+        );
+        let pb = ProgressBar::new(files.len() as u64);
+        for file in files.iter() {
+            client.open(file, &args.language).await?;
+            pb.inc(1);
+        }
+        pb.finish_and_clear();
+    }
+
+    println!(
+        "{} {}Walking call hierarchy...",
+        style("[3/3]").bold().dim(),
+        PAPER
+    );
+    let mut symbol_map = LspIndexer::new(&root).index(&mut client).await?;
+    symbol_map.header = Some(header);
+    client.shutdown().await?;
+    server.wait().await?;
+
+    symbol_map.save(&args.output)
+}
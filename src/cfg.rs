@@ -1,11 +1,145 @@
+use std::collections::{HashMap, HashSet};
 use std::iter::Iterator;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::{Arc, OnceLock};
 
-use crate::symbols::{Symbol, SymbolId, SymbolMap};
-use petgraph::graphmap::DiGraphMap;
+use crate::index::Index;
+use crate::symbols::{name_matches, EdgeKind, IndexHeader, Linkage, Reference, Symbol, SymbolId, SymbolMap};
+use itertools::Either;
+use petgraph::algo::{all_simple_paths, astar};
+use petgraph::graph::{DiGraph, NodeIndex};
+use petgraph::visit::{EdgeFiltered, EdgeRef};
+use petgraph::Direction::{Incoming, Outgoing};
 
+/// Compact integer identifier of a symbol in the graph of a
+/// [`ControlFlowGraph`]
+pub type NodeId = NodeIndex<u32>;
+
+/// Set of edge kinds
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct EdgeKinds(u8);
+
+impl EdgeKinds {
+    pub const NONE: EdgeKinds = EdgeKinds(0);
+
+    pub fn all() -> Self {
+        EdgeKind::ALL.into_iter().collect()
+    }
+
+    /// Kinds through which a function may run another, leaving out the
+    /// references that only take an address
+    pub fn calls() -> Self {
+        [EdgeKind::Call, EdgeKind::Virtual, EdgeKind::Indirect].into_iter().collect()
+    }
+
+    pub(crate) fn bits(&self) -> u8 {
+        self.0
+    }
+
+    pub(crate) fn from_bits(bits: u8) -> Self {
+        EdgeKinds(bits)
+    }
+
+    fn bit(kind: EdgeKind) -> u8 {
+        1 << (kind as u8)
+    }
+
+    pub fn insert(&mut self, kind: EdgeKind) {
+        self.0 |= Self::bit(kind);
+    }
+
+    pub fn remove(&mut self, kind: EdgeKind) {
+        self.0 &= !Self::bit(kind);
+    }
+
+    pub fn contains(&self, kind: EdgeKind) -> bool {
+        self.0 & Self::bit(kind) != 0
+    }
+
+    pub fn intersects(&self, other: EdgeKinds) -> bool {
+        self.0 & other.0 != 0
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = EdgeKind> + '_ {
+        EdgeKind::ALL.into_iter().filter(|kind| self.contains(*kind))
+    }
+}
+
+impl FromIterator<EdgeKind> for EdgeKinds {
+    fn from_iter<I: IntoIterator<Item = EdgeKind>>(iter: I) -> Self {
+        let mut kinds = EdgeKinds::NONE;
+        for kind in iter {
+            kinds.insert(kind);
+        }
+        kinds
+    }
+}
+
+/// Comma-separated kind names, such as `call,virtual`
+impl FromStr for EdgeKinds {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        s.split(',')
+            .map(|name| {
+                EdgeKind::from_name(name.trim()).ok_or_else(|| {
+                    anyhow::anyhow!(
+                        "Unknown edge kind: {}, expected one of: {}",
+                        name,
+                        EdgeKind::ALL.map(|kind| kind.name()).join(", ")
+                    )
+                })
+            })
+            .collect()
+    }
+}
+
+/// Which way a nested scope walks the call graph
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// From a function to the functions it calls
+    Callees,
+    /// From a function to the functions that call it
+    Callers,
+}
+
+impl Direction {
+    /// Orient an edge between a symbol and a symbol related to it in this
+    /// direction, so that the edge always points from the caller to the callee
+    pub fn edge(&self, symbol: SymbolId, related: SymbolId) -> (SymbolId, SymbolId) {
+        match self {
+            Direction::Callees => (symbol, related),
+            Direction::Callers => (related, symbol),
+        }
+    }
+}
+
+/// Call graph over the symbols of an index
+///
+/// Every symbol, including the ones only known as callees, gets a node in a
+/// petgraph graph, which keeps both the outgoing and the incoming edges of each
+/// node. Each edge is weighted by the kinds of references it stands for.
+/// Graph algorithms can run on [`ControlFlowGraph::graph`] directly and map
+/// the results back with [`ControlFlowGraph::symbol_id`].
+///
+/// Over a binary index, callees and callers are read from the adjacency lists
+/// of the index, and the petgraph graph is only built the first time
+/// [`ControlFlowGraph::graph`] is called, such as to search paths or cycles.
 #[derive(Debug, Clone)]
 pub struct ControlFlowGraph {
-    pub symbols: SymbolMap,
+    symbols: SymbolStore,
+    graph: OnceLock<DiGraph<SymbolId, EdgeKinds>>,
+    node_ids: HashMap<SymbolId, NodeId>,
+}
+
+/// Where the details of the symbols come from
+#[derive(Debug, Clone)]
+enum SymbolStore {
+    Map(Box<SymbolMap>),
+    /// Binary index, whose ids and symbols are decoded on first use. Nodes of
+    /// the graph are numbered like the symbols of the index.
+    Index(Arc<Index>, Vec<OnceLock<SymbolId>>, Vec<OnceLock<Option<Symbol>>>),
 }
 
 #[derive(Debug, Clone)]
@@ -15,23 +149,396 @@ pub struct NodeList(pub Vec<SymbolId>);
 pub struct EdgeList(pub Vec<(SymbolId, SymbolId)>);
 
 impl ControlFlowGraph {
+    /// Load an index, binary or JSON
+    pub fn load<P: AsRef<Path>>(path: P) -> anyhow::Result<Self> {
+        if Index::is_index(&path)? {
+            Ok(Self::from_index(Index::open(path)?))
+        } else {
+            Ok(Self::from_symbols(SymbolMap::load(path)?))
+        }
+    }
+
+    /// Query an index in place, decoding ids and symbols when they are
+    /// needed
+    pub fn from_index(index: Index) -> Self {
+        let ids = (0..index.len()).map(|_| OnceLock::new()).collect();
+        let symbols = (0..index.len()).map(|_| OnceLock::new()).collect();
+        Self {
+            symbols: SymbolStore::Index(Arc::new(index), ids, symbols),
+            graph: OnceLock::new(),
+            node_ids: HashMap::new(),
+        }
+    }
+
     pub fn from_symbols(symbols: SymbolMap) -> Self {
-        let mut g = DiGraphMap::new();
+        let mut graph = DiGraph::default();
+        let mut node_ids = HashMap::new();
+        let mut add_node = |graph: &mut DiGraph<SymbolId, EdgeKinds>, symbol_id: &SymbolId| {
+            *node_ids
+                .entry(symbol_id.clone())
+                .or_insert_with(|| graph.add_node(symbol_id.clone()))
+        };
+
         for (parent_l, s) in symbols.iter() {
-            for child_l in s.children.iter() {
-                g.add_edge(parent_l, child_l, ());
+            let parent = add_node(&mut graph, parent_l);
+            for (child_l, references) in s.children.iter() {
+                let child = add_node(&mut graph, child_l);
+                let kinds = references.iter().map(|r| r.kind).collect();
+                graph.add_edge(parent, child, kinds);
             }
         }
         Self {
-            symbols: symbols,
+            symbols: SymbolStore::Map(Box::new(symbols)),
+            graph: OnceLock::from(graph),
+            node_ids,
+        }
+    }
+
+    /// The underlying graph, with an edge from every caller to its callees
+    pub fn graph(&self) -> &DiGraph<SymbolId, EdgeKinds> {
+        self.graph.get_or_init(|| {
+            let index = match &self.symbols {
+                SymbolStore::Index(index, _, _) => index,
+                // Built along with the graph
+                SymbolStore::Map(_) => unreachable!(),
+            };
+            let mut graph = DiGraph::with_capacity(index.len(), index.edge_count());
+            for node in 0..index.len() {
+                graph.add_node(self.symbol_id(NodeId::new(node)).clone());
+            }
+            for node in 0..index.len() as u32 {
+                for (child, kinds) in index.callees(node) {
+                    graph.add_edge(NodeId::new(node as usize), NodeId::new(child as usize), kinds);
+                }
+            }
+            graph
+        })
+    }
+
+    /// Number of symbols, including the ones only known as callees
+    pub fn node_count(&self) -> usize {
+        match &self.symbols {
+            SymbolStore::Map(_) => self.graph().node_count(),
+            SymbolStore::Index(index, _, _) => index.len(),
+        }
+    }
+
+    /// Number of pairs of symbols with references from one to the other
+    pub fn edge_count(&self) -> usize {
+        match &self.symbols {
+            SymbolStore::Map(_) => self.graph().edge_count(),
+            SymbolStore::Index(index, _, _) => index.edge_count(),
+        }
+    }
+
+    /// Ids of every symbol, including the ones only known as callees
+    pub fn all_symbol_ids(&self) -> impl Iterator<Item = &SymbolId> {
+        (0..self.node_count()).map(|node| self.symbol_id(NodeId::new(node)))
+    }
+
+    pub fn node_id(&self, symbol_id: &SymbolId) -> Option<NodeId> {
+        match &self.symbols {
+            SymbolStore::Map(_) => self.node_ids.get(symbol_id).copied(),
+            SymbolStore::Index(index, _, _) => index.find(symbol_id.as_str()).map(|node| NodeId::new(node as usize)),
+        }
+    }
+
+    pub fn symbol_id(&self, node: NodeId) -> &SymbolId {
+        match &self.symbols {
+            SymbolStore::Map(_) => &self.graph()[node],
+            SymbolStore::Index(index, ids, _) => {
+                ids[node.index()].get_or_init(|| SymbolId::new(index.id(node.index() as u32).to_string()))
+            }
         }
     }
 
+    /// Symbols defined in the index
     pub fn iter_symbols(&self) -> impl Iterator<Item = (&SymbolId, &Symbol)> {
-        self.symbols.iter()
+        match &self.symbols {
+            SymbolStore::Map(symbols) => Either::Left(symbols.iter()),
+            SymbolStore::Index(index, _, _) => Either::Right(
+                (0..index.len())
+                    .map(NodeId::new)
+                    .filter_map(|node| Some((self.symbol_id(node), self.decoded(node)?))),
+            ),
+        }
+    }
+
+    /// Where and how the index was generated
+    pub fn header(&self) -> Option<&IndexHeader> {
+        match &self.symbols {
+            SymbolStore::Map(symbols) => symbols.header.as_ref(),
+            SymbolStore::Index(index, _, _) => index.header(),
+        }
+    }
+
+    /// Path of a source file relative to the project root recorded in the
+    /// index, unchanged if it lies elsewhere
+    pub fn project_path<'a>(&self, file: &'a str) -> &'a Path {
+        let file = Path::new(file);
+        let file = file.strip_prefix("./").unwrap_or(file);
+        self.header()
+            .and_then(|header| header.project_root.as_deref())
+            .and_then(|root| file.strip_prefix(root).ok())
+            .unwrap_or(file)
+    }
+
+    /// Ids of the symbols defined in the index
+    pub fn symbol_ids(&self) -> impl Iterator<Item = &SymbolId> {
+        match &self.symbols {
+            SymbolStore::Map(symbols) => Either::Left(symbols.map.keys()),
+            SymbolStore::Index(index, _, _) => Either::Right(
+                (0..index.len() as u32)
+                    .filter(|node| index.is_defined(*node))
+                    .map(|node| self.symbol_id(NodeId::new(node as usize))),
+            ),
+        }
+    }
+
+    /// Name and scope of a defined symbol, read without decoding the rest of
+    /// the symbol
+    pub fn symbol_name(&self, id: &SymbolId) -> Option<(&str, Option<&str>)> {
+        match &self.symbols {
+            SymbolStore::Map(symbols) => symbols
+                .map
+                .get(id)
+                .map(|symbol| (symbol.name.as_str(), symbol.scope.as_deref())),
+            SymbolStore::Index(index, _, _) => {
+                let node = self.node_id(id)?.index() as u32;
+                Some((index.name(node)?, index.scope(node)))
+            }
+        }
+    }
+
+    /// File the body of a symbol is in, `None` if the symbol is only declared
+    pub fn definition_file(&self, id: &SymbolId) -> Option<&str> {
+        match &self.symbols {
+            SymbolStore::Map(symbols) => symbols
+                .map
+                .get(id)
+                .and_then(|symbol| symbol.definition.as_ref())
+                .map(|range| range.begin.file.as_str()),
+            SymbolStore::Index(index, _, _) => index.definition_file(self.node_id(id)?.index() as u32),
+        }
+    }
+
+    /// Whether a symbol is defined in the index and visible to other files
+    pub fn is_exported(&self, id: &SymbolId) -> bool {
+        match &self.symbols {
+            SymbolStore::Map(symbols) => symbols.map.get(id).is_some_and(|symbol| symbol.linkage == Linkage::External),
+            SymbolStore::Index(index, _, _) => index
+                .find(id.as_str())
+                .is_some_and(|node| index.is_defined(node) && !index.is_internal(node)),
+        }
+    }
+
+    /// Whether a function takes the address of a symbol, see
+    /// [`ControlFlowGraph::stored_functions`] for addresses stored by
+    /// initializers
+    pub fn is_address_taken(&self, id: &SymbolId) -> bool {
+        let taken: EdgeKinds = [EdgeKind::AddressTaken, EdgeKind::CallbackArgument].into_iter().collect();
+        self.node_id(id)
+            .is_some_and(|node| self.edges(node, Incoming).any(|(_, kinds)| kinds.intersects(taken)))
+    }
+
+    /// Symbols stored in any struct field
+    pub fn stored_functions(&self) -> HashSet<&SymbolId> {
+        match &self.symbols {
+            SymbolStore::Map(symbols) => symbols.fields.values().flatten().collect(),
+            SymbolStore::Index(index, _, _) => index
+                .stored_functions()
+                .map(|node| self.symbol_id(NodeId::new(node as usize)))
+                .collect(),
+        }
     }
 
     pub fn get_symbol(&self, loc: &SymbolId) -> Option<&Symbol> {
-        self.symbols.map.get(loc)
+        match &self.symbols {
+            SymbolStore::Map(symbols) => symbols.map.get(loc),
+            SymbolStore::Index(_, _, _) => self.decoded(self.node_id(loc)?),
+        }
+    }
+
+    fn decoded(&self, node: NodeId) -> Option<&Symbol> {
+        match &self.symbols {
+            SymbolStore::Map(symbols) => symbols.map.get(self.symbol_id(node)),
+            SymbolStore::Index(index, _, symbols) => symbols[node.index()]
+                .get_or_init(|| index.symbol(node.index() as u32))
+                .as_ref(),
+        }
+    }
+
+    /// Where and how `from` refers to `to`
+    pub fn call_sites(&self, from: &SymbolId, to: &SymbolId) -> &[Reference] {
+        self.get_symbol(from)
+            .and_then(|symbol| symbol.children.get(to))
+            .map_or(&[], |sites| sites.as_slice())
+    }
+
+    /// Kinds of references from `from` to `to`
+    pub fn edge_kinds(&self, from: &SymbolId, to: &SymbolId) -> EdgeKinds {
+        match (self.node_id(from), self.node_id(to)) {
+            (Some(from), Some(to)) => self
+                .edges(from, Outgoing)
+                .find(|(node, _)| *node == to)
+                .map_or(EdgeKinds::NONE, |(_, kinds)| kinds),
+            _ => EdgeKinds::NONE,
+        }
+    }
+
+    /// All symbols with the given name, qualified or not, or the symbol only
+    /// known as a callee with the given id
+    pub fn find_symbols(&self, name: &str) -> Vec<SymbolId> {
+        let mut found: Vec<SymbolId> = match &self.symbols {
+            SymbolStore::Map(_) => self
+                .iter_symbols()
+                .filter(|(_, symbol)| symbol.has_name(name))
+                .map(|(id, _)| id.clone())
+                .collect(),
+            // Names are read from the index without decoding the symbols
+            SymbolStore::Index(index, _, _) => (0..index.len() as u32)
+                .filter(|node| {
+                    index
+                        .name(*node)
+                        .is_some_and(|symbol_name| name_matches(symbol_name, index.scope(*node), name))
+                })
+                .map(|node| SymbolId::new(index.id(node).to_string()))
+                .collect(),
+        };
+        let callee = SymbolId::new(name.to_string());
+        if self.node_id(&callee).is_some() && self.symbol_name(&callee).is_none() {
+            found.push(callee);
+        }
+        found.sort();
+        found
+    }
+
+    /// Nodes one edge away from `node` in `direction`, with the kinds of the
+    /// edge
+    fn edges(&self, node: NodeId, direction: petgraph::Direction) -> impl Iterator<Item = (NodeId, EdgeKinds)> + '_ {
+        match &self.symbols {
+            SymbolStore::Map(_) => Either::Left(self.graph().edges_directed(node, direction).map(move |edge| {
+                let related = match direction {
+                    Outgoing => edge.target(),
+                    Incoming => edge.source(),
+                };
+                (related, *edge.weight())
+            })),
+            SymbolStore::Index(index, _, _) => {
+                let node = node.index() as u32;
+                let edges = match direction {
+                    Outgoing => Either::Left(index.callees(node)),
+                    Incoming => Either::Right(index.callers(node)),
+                };
+                Either::Right(edges.map(|(related, kinds)| (NodeId::new(related as usize), kinds)))
+            }
+        }
+    }
+
+    fn neighbors_directed(&self, symbol_id: &SymbolId, direction: petgraph::Direction, kinds: EdgeKinds) -> Vec<SymbolId> {
+        let node = match self.node_id(symbol_id) {
+            Some(node) => node,
+            None => return vec![],
+        };
+
+        self.edges(node, direction)
+            .filter(|(_, edge_kinds)| edge_kinds.intersects(kinds))
+            .map(|(related, _)| self.symbol_id(related).clone())
+            .collect()
+    }
+
+    /// Functions called by `symbol_id`
+    pub fn neighbors(&self, symbol_id: &SymbolId) -> Vec<SymbolId> {
+        self.neighbors_directed(symbol_id, Outgoing, EdgeKinds::all())
+    }
+
+    /// Functions calling `symbol_id`
+    pub fn predecessors(&self, symbol_id: &SymbolId) -> Vec<SymbolId> {
+        self.neighbors_directed(symbol_id, Incoming, EdgeKinds::all())
+    }
+
+    /// Number of distinct functions called by `symbol_id`
+    pub fn out_degree(&self, symbol_id: &SymbolId) -> usize {
+        self.node_id(symbol_id).map_or(0, |node| self.edges(node, Outgoing).count())
+    }
+
+    /// Number of distinct functions calling `symbol_id`
+    pub fn in_degree(&self, symbol_id: &SymbolId) -> usize {
+        self.node_id(symbol_id).map_or(0, |node| self.edges(node, Incoming).count())
+    }
+
+    /// Symbols one reference of the given kinds away from `symbol_id` in the
+    /// given direction
+    pub fn get_related(&self, symbol_id: &SymbolId, direction: Direction, kinds: EdgeKinds) -> Vec<SymbolId> {
+        match direction {
+            Direction::Callees => self.neighbors_directed(symbol_id, Outgoing, kinds),
+            Direction::Callers => self.neighbors_directed(symbol_id, Incoming, kinds),
+        }
+    }
+
+    /// The graph induced by `symbol_ids`: only these symbols and the calls
+    /// between them are kept
+    pub fn subgraph(&self, symbol_ids: &[SymbolId]) -> ControlFlowGraph {
+        let keep: HashSet<&SymbolId> = symbol_ids.iter().collect();
+        let mut symbols = SymbolMap::new();
+        for symbol_id in symbol_ids.iter() {
+            if let Some(symbol) = self.get_symbol(symbol_id) {
+                let mut symbol = symbol.clone();
+                symbol.children.retain(|child, _| keep.contains(child));
+                symbols.map.insert(symbol_id.clone(), symbol);
+            }
+        }
+        ControlFlowGraph::from_symbols(symbols)
+    }
+
+    fn path_edges(&self, path: &[NodeId]) -> Vec<(SymbolId, SymbolId)> {
+        path.windows(2)
+            .map(|pair| (self.symbol_id(pair[0]).clone(), self.symbol_id(pair[1]).clone()))
+            .collect()
+    }
+
+    /// The shortest chain of references of the given kinds from any of `from`
+    /// to any of `to`
+    pub fn shortest_path(&self, from: &[SymbolId], to: &[SymbolId], kinds: EdgeKinds) -> Option<EdgeList> {
+        let goals: HashSet<NodeId> = to.iter().filter_map(|id| self.node_id(id)).collect();
+        let graph = EdgeFiltered::from_fn(self.graph(), |edge| edge.weight().intersects(kinds));
+
+        from.iter()
+            .filter_map(|id| self.node_id(id))
+            .filter_map(|start| {
+                astar(&graph, start, |n| goals.contains(&n), |_| 1, |_| 0)
+            })
+            .min_by_key(|(cost, _)| *cost)
+            .map(|(_, path)| EdgeList(self.path_edges(&path)))
+    }
+
+    /// Every edge lying on a simple chain of references of the given kinds
+    /// from any of `from` to any of `to` that has at most `max_calls` calls
+    pub fn all_simple_paths(
+        &self,
+        from: &[SymbolId],
+        to: &[SymbolId],
+        kinds: EdgeKinds,
+        max_calls: Option<usize>,
+    ) -> EdgeList {
+        let graph = EdgeFiltered::from_fn(self.graph(), |edge| edge.weight().intersects(kinds));
+        let mut edges = vec![];
+        let max_intermediate = match max_calls {
+            Some(0) => return EdgeList(edges),
+            max_calls => max_calls.map(|calls| calls - 1),
+        };
+        for start in from.iter().filter_map(|id| self.node_id(id)) {
+            for goal in to.iter().filter_map(|id| self.node_id(id)) {
+                if start == goal {
+                    continue;
+                }
+                for path in all_simple_paths::<Vec<_>, _>(&graph, start, goal, 0, max_intermediate) {
+                    edges.extend(self.path_edges(&path));
+                }
+            }
+        }
+        edges.sort();
+        edges.dedup();
+        EdgeList(edges)
     }
 }
@@ -0,0 +1,123 @@
+use anyhow::{anyhow, bail, Context, Result};
+use glob::{MatchOptions, Pattern};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// Group of the symbols without a location, typically library functions
+pub const EXTERNAL: &str = "(external)";
+/// Group of the files no module of a [`ModuleMap`] claims
+pub const OTHER: &str = "(other)";
+
+/// Named sets of files, read from lines like
+///
+/// ```text
+/// # Comment
+/// net: src/net/** include/net/*.h
+/// mm: src/mm/**
+/// ```
+///
+/// Patterns match paths relative to the project root, with `*` staying within
+/// a directory and `**` crossing them. A file belongs to the first module
+/// with a matching pattern.
+#[derive(Debug, Clone)]
+pub struct ModuleMap {
+    modules: Vec<(String, Vec<Pattern>)>,
+}
+
+impl ModuleMap {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path).with_context(|| format!("Cannot read module map {:?}", path))?;
+        text.parse().with_context(|| format!("Invalid module map {:?}", path))
+    }
+
+    /// Name of the module containing `file`
+    pub fn module(&self, file: &Path) -> Option<&str> {
+        let options = MatchOptions {
+            require_literal_separator: true,
+            ..MatchOptions::new()
+        };
+        self.modules
+            .iter()
+            .find(|(_, patterns)| patterns.iter().any(|pattern| pattern.matches_path_with(file, options)))
+            .map(|(name, _)| name.as_str())
+    }
+}
+
+impl FromStr for ModuleMap {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut modules = vec![];
+        for (number, line) in s.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (name, patterns) = line
+                .split_once(':')
+                .ok_or_else(|| anyhow!("Line {}: expected \"MODULE: PATTERN...\"", number + 1))?;
+            let patterns = patterns
+                .split_whitespace()
+                .map(|pattern| {
+                    let pattern = pattern.strip_prefix("./").unwrap_or(pattern);
+                    Pattern::new(pattern).map_err(|e| anyhow!("Line {}: invalid pattern \"{}\": {}", number + 1, pattern, e))
+                })
+                .collect::<Result<Vec<_>>>()?;
+            modules.push((name.trim().to_string(), patterns));
+        }
+        Ok(Self { modules })
+    }
+}
+
+/// How symbols are grouped by their location
+#[derive(Debug, Clone)]
+pub enum Grouping {
+    /// By source file
+    File,
+    /// By directory, cut to the first `depth` components if given
+    Dir { depth: Option<usize> },
+    /// By the modules of a user-defined map
+    Modules(ModuleMap),
+}
+
+impl Grouping {
+    pub const SYNTAX: &'static str = "file, dir, dir:DEPTH or modules:FILE";
+
+    /// Name of the group of a file, given relative to the project root
+    pub fn group(&self, file: &Path) -> String {
+        match self {
+            Grouping::File => file.to_string_lossy().into_owned(),
+            Grouping::Dir { depth } => {
+                let dir = file.parent().unwrap_or_else(|| Path::new(""));
+                let dir: PathBuf = match depth {
+                    Some(depth) => dir.components().take(*depth).collect(),
+                    None => dir.to_path_buf(),
+                };
+                if dir.as_os_str().is_empty() {
+                    ".".to_string()
+                } else {
+                    dir.to_string_lossy().into_owned()
+                }
+            }
+            Grouping::Modules(modules) => modules.module(file).unwrap_or(OTHER).to_string(),
+        }
+    }
+}
+
+impl FromStr for Grouping {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s.split_once(':') {
+            None if s == "file" => Grouping::File,
+            None if s == "dir" => Grouping::Dir { depth: None },
+            Some(("dir", depth)) => match depth.parse() {
+                Ok(depth) if depth > 0 => Grouping::Dir { depth: Some(depth) },
+                _ => bail!("Directory depth must be a positive number, got \"{}\"", depth),
+            },
+            Some(("modules", path)) => Grouping::Modules(ModuleMap::load(path)?),
+            _ => bail!("Unknown grouping: {}, expected one of: {}", s, Grouping::SYNTAX),
+        })
+    }
+}
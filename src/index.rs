@@ -0,0 +1,706 @@
+//! Binary index format
+//!
+//! An index file is a header listing its sections, followed by the sections,
+//! which are arrays of little-endian `u32`. Every string is stored once in a
+//! string table and referred to by number. Symbols, including the ones only
+//! known as callees, are numbered in the order of their ids, and what belongs
+//! to a symbol is stored in CSR form: the edges of symbol `n` are the rows
+//! `offsets[n]..offsets[n + 1]` of the edge table. Nothing but the
+//! [`IndexHeader`], which is stored as JSON, is decoded when an index is
+//! opened, so a memory-mapped index is ready to be queried at once.
+
+use crate::cfg::EdgeKinds;
+use crate::symbols::{
+    Class, EdgeKind, IndexHeader, Linkage, Reference, SourceLocation, SourceRange, Symbol, SymbolId,
+    SymbolMap,
+};
+use anyhow::{bail, Context, Result};
+use memmap2::Mmap;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::ops::Range;
+use std::path::Path;
+
+const MAGIC: &[u8; 8] = b"ASKLIDX\0";
+/// Version of the layout of the file, unlike [`crate::symbols::SCHEMA_VERSION`]
+/// which versions its contents
+const VERSION: u32 = 3;
+/// Stands for a missing string or range
+const NONE: u32 = u32::MAX;
+
+/// Sections of an index, in the order of the header
+#[derive(Clone, Copy, Debug)]
+enum Section {
+    /// [`IndexHeader`] as JSON
+    Header,
+    StringOffsets,
+    Strings,
+    /// Id, name, scope, flags and definition range of each symbol, all but
+    /// the id are only set for defined symbols
+    Nodes,
+    /// Begin file, line and column, then end file, line and column
+    Ranges,
+    SymbolRangeOffsets,
+    SymbolRanges,
+    EdgeOffsets,
+    /// Callee and kinds of each edge
+    Edges,
+    SiteOffsets,
+    /// Kind, range and field of each reference an edge stands for
+    Sites,
+    CallerOffsets,
+    /// Caller and edge number of each incoming edge
+    Callers,
+    OverrideOffsets,
+    Overrides,
+    FieldCallOffsets,
+    /// Kind, range and field of each call through a field
+    FieldCalls,
+    Classes,
+    BaseOffsets,
+    Bases,
+    Fields,
+    StoreOffsets,
+    Stores,
+}
+
+const SECTIONS: usize = Section::Stores as usize + 1;
+const HEADER_LEN: usize = MAGIC.len() + 8 + SECTIONS * 16;
+
+const NODE_WIDTH: usize = 5;
+const RANGE_WIDTH: usize = 6;
+const EDGE_WIDTH: usize = 2;
+const SITE_WIDTH: usize = 3;
+const CALLER_WIDTH: usize = 2;
+
+const FLAG_DEFINED: u32 = 1;
+const FLAG_INTERNAL: u32 = 2;
+
+/// Builds the sections of an index
+#[derive(Default)]
+struct Writer<'a> {
+    strings: HashMap<&'a str, u32>,
+    string_bytes: Vec<u8>,
+    ranges: HashMap<&'a SourceRange, u32>,
+    sections: Vec<Vec<u32>>,
+}
+
+impl<'a> Writer<'a> {
+    fn new() -> Self {
+        Self {
+            sections: vec![vec![]; SECTIONS],
+            ..Default::default()
+        }
+    }
+
+    fn push(&mut self, section: Section, values: &[u32]) {
+        self.sections[section as usize].extend_from_slice(values);
+    }
+
+    /// Start the rows belonging to the next element
+    fn start(&mut self, offsets: Section, rows: Section, width: usize) {
+        let len = (self.sections[rows as usize].len() / width) as u32;
+        self.push(offsets, &[len]);
+    }
+
+    fn string(&mut self, string: &'a str) -> u32 {
+        if let Some(number) = self.strings.get(string) {
+            return *number;
+        }
+        let number = self.strings.len() as u32;
+        self.push(Section::StringOffsets, &[self.string_bytes.len() as u32]);
+        self.string_bytes.extend_from_slice(string.as_bytes());
+        self.strings.insert(string, number);
+        number
+    }
+
+    fn range(&mut self, range: Option<&'a SourceRange>) -> u32 {
+        let range = match range {
+            Some(range) => range,
+            None => return NONE,
+        };
+        if let Some(number) = self.ranges.get(range) {
+            return *number;
+        }
+        let number = self.ranges.len() as u32;
+        let row = [
+            self.string(&range.begin.file),
+            range.begin.line as u32,
+            range.begin.col as u32,
+            self.string(&range.end.file),
+            range.end.line as u32,
+            range.end.col as u32,
+        ];
+        self.push(Section::Ranges, &row);
+        self.ranges.insert(range, number);
+        number
+    }
+
+    fn reference(&mut self, section: Section, reference: &'a Reference) {
+        let row = [
+            reference.kind as u32,
+            self.range(reference.range.as_ref()),
+            reference.via.as_deref().map_or(NONE, |via| self.string(via)),
+        ];
+        self.push(section, &row);
+    }
+
+    fn write(mut self, header: Option<&IndexHeader>, out: &mut dyn Write) -> Result<()> {
+        self.push(Section::StringOffsets, &[self.string_bytes.len() as u32]);
+        let string_bytes = std::mem::take(&mut self.string_bytes);
+
+        let mut sections: Vec<Vec<u8>> = self
+            .sections
+            .iter()
+            .map(|section| section.iter().flat_map(|value| value.to_le_bytes()).collect())
+            .collect();
+        sections[Section::Strings as usize] = string_bytes;
+        sections[Section::Header as usize] = serde_json::to_vec(&header)?;
+
+        out.write_all(MAGIC)?;
+        out.write_all(&VERSION.to_le_bytes())?;
+        out.write_all(&(SECTIONS as u32).to_le_bytes())?;
+        let mut offset = HEADER_LEN;
+        for section in sections.iter() {
+            out.write_all(&(offset as u64).to_le_bytes())?;
+            out.write_all(&(section.len() as u64).to_le_bytes())?;
+            offset += padded(section.len());
+        }
+        for section in sections.iter() {
+            out.write_all(section)?;
+            out.write_all(&[0; 3][..padded(section.len()) - section.len()])?;
+        }
+        Ok(())
+    }
+}
+
+/// Sections start at multiples of four bytes
+fn padded(len: usize) -> usize {
+    (len + 3) & !3
+}
+
+/// Index file mapped into memory
+#[derive(Debug)]
+pub struct Index {
+    mmap: Mmap,
+    /// Position and length in bytes of each section
+    sections: Vec<(usize, usize)>,
+    header: Option<IndexHeader>,
+}
+
+impl Index {
+    /// Whether a file starts like an index, as opposed to a JSON index
+    pub fn is_index<P: AsRef<Path>>(path: P) -> Result<bool> {
+        let mut magic = [0; MAGIC.len()];
+        let mut file = File::open(path)?;
+        Ok(file.read(&mut magic)? == magic.len() && &magic == MAGIC)
+    }
+
+    /// Map an index file, checking that its numbers all refer to something
+    /// within it
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let file = File::open(path)?;
+        // SAFETY: the index must not be modified while it is mapped, which
+        // askl does not do, as `SymbolMap::save` writes a new file and
+        // renames it over the old one, leaving the mapped file as it was
+        let mmap = unsafe { Mmap::map(&file)? };
+        let index = Self::from_mmap(mmap).with_context(|| format!("Cannot read index {:?}", path))?;
+        IndexHeader::check_optional(index.header.as_ref(), path)?;
+        Ok(index)
+    }
+
+    fn from_mmap(mmap: Mmap) -> Result<Self> {
+        if mmap.len() < HEADER_LEN || &mmap[..MAGIC.len()] != MAGIC {
+            bail!("Not an askl index");
+        }
+        let header = |i: usize| u64::from_le_bytes(mmap[i..i + 8].try_into().unwrap()) as usize;
+        let words = |i: usize| u32::from_le_bytes(mmap[i..i + 4].try_into().unwrap());
+
+        let version = words(MAGIC.len());
+        if version != VERSION {
+            bail!(
+                "The index has format version {}, but askl {} reads version {}; regenerate it with gen-ast or \
+                 lsp-index, or convert it to JSON with the askl that wrote it and back with this one",
+                version,
+                env!("CARGO_PKG_VERSION"),
+                VERSION
+            );
+        }
+        if words(MAGIC.len() + 4) as usize != SECTIONS {
+            bail!("Unexpected number of sections");
+        }
+
+        let mut sections = Vec::with_capacity(SECTIONS);
+        for i in 0..SECTIONS {
+            let entry = MAGIC.len() + 8 + i * 16;
+            let (offset, len) = (header(entry), header(entry + 8));
+            if offset % 4 != 0 || offset.checked_add(len).is_none_or(|end| end > mmap.len()) {
+                bail!("Section {} out of bounds", i);
+            }
+            sections.push((offset, len));
+        }
+        let mut index = Self {
+            mmap,
+            sections,
+            header: None,
+        };
+        index.header = serde_json::from_slice(index.section(Section::Header)).context("Corrupt header")?;
+        index.validate()?;
+        Ok(index)
+    }
+
+    /// Check every table and every number in them, so that reading the index
+    /// cannot go out of bounds
+    fn validate(&self) -> Result<()> {
+        let strings = self.section(Section::Strings);
+        if std::str::from_utf8(strings).is_err() {
+            bail!("Strings are not UTF-8");
+        }
+        let tables = [
+            (Section::StringOffsets, 1),
+            (Section::Nodes, NODE_WIDTH),
+            (Section::Ranges, RANGE_WIDTH),
+            (Section::SymbolRangeOffsets, 1),
+            (Section::SymbolRanges, 1),
+            (Section::EdgeOffsets, 1),
+            (Section::Edges, EDGE_WIDTH),
+            (Section::SiteOffsets, 1),
+            (Section::Sites, SITE_WIDTH),
+            (Section::CallerOffsets, 1),
+            (Section::Callers, CALLER_WIDTH),
+            (Section::OverrideOffsets, 1),
+            (Section::Overrides, 1),
+            (Section::FieldCallOffsets, 1),
+            (Section::FieldCalls, SITE_WIDTH),
+            (Section::Classes, 1),
+            (Section::BaseOffsets, 1),
+            (Section::Bases, 1),
+            (Section::Fields, 1),
+            (Section::StoreOffsets, 1),
+            (Section::Stores, 1),
+        ];
+        for (section, width) in tables {
+            if !self.section(section).len().is_multiple_of(4 * width) {
+                bail!("Corrupt {:?} section", section);
+            }
+        }
+
+        let string_count = match self.row_count(Section::StringOffsets, 1).checked_sub(1) {
+            Some(count) => count,
+            None => bail!("Corrupt {:?} section", Section::StringOffsets),
+        };
+        let nodes = self.len();
+        let ranges = self.row_count(Section::Ranges, RANGE_WIDTH);
+        let edges = self.row_count(Section::Edges, EDGE_WIDTH);
+        let classes = self.row_count(Section::Classes, 1);
+        let fields = self.row_count(Section::Fields, 1);
+        self.check_offsets(Section::StringOffsets, string_count, strings.len())?;
+        self.check_offsets(Section::SymbolRangeOffsets, nodes, self.row_count(Section::SymbolRanges, 1))?;
+        self.check_offsets(Section::EdgeOffsets, nodes, edges)?;
+        self.check_offsets(Section::SiteOffsets, edges, self.row_count(Section::Sites, SITE_WIDTH))?;
+        self.check_offsets(Section::CallerOffsets, nodes, self.row_count(Section::Callers, CALLER_WIDTH))?;
+        self.check_offsets(Section::OverrideOffsets, nodes, self.row_count(Section::Overrides, 1))?;
+        self.check_offsets(Section::FieldCallOffsets, nodes, self.row_count(Section::FieldCalls, SITE_WIDTH))?;
+        self.check_offsets(Section::BaseOffsets, classes, self.row_count(Section::Bases, 1))?;
+        self.check_offsets(Section::StoreOffsets, fields, self.row_count(Section::Stores, 1))?;
+
+        let kinds = EdgeKind::ALL.len();
+        let columns = [
+            (Section::Nodes, NODE_WIDTH, 0, string_count, false),
+            (Section::Nodes, NODE_WIDTH, 1, string_count, true),
+            (Section::Nodes, NODE_WIDTH, 2, string_count, true),
+            (Section::Nodes, NODE_WIDTH, 4, ranges, true),
+            (Section::Ranges, RANGE_WIDTH, 0, string_count, false),
+            (Section::Ranges, RANGE_WIDTH, 3, string_count, false),
+            (Section::SymbolRanges, 1, 0, ranges, true),
+            (Section::Edges, EDGE_WIDTH, 0, nodes, false),
+            (Section::Edges, EDGE_WIDTH, 1, 1 << kinds, false),
+            (Section::Callers, CALLER_WIDTH, 0, nodes, false),
+            (Section::Callers, CALLER_WIDTH, 1, edges, false),
+            (Section::Overrides, 1, 0, nodes, false),
+            (Section::Classes, 1, 0, string_count, false),
+            (Section::Bases, 1, 0, string_count, false),
+            (Section::Fields, 1, 0, string_count, false),
+            (Section::Stores, 1, 0, nodes, false),
+        ];
+        for (section, width, column, bound, optional) in columns {
+            self.check_column(section, width, column, bound, optional)?;
+        }
+        for section in [Section::Sites, Section::FieldCalls] {
+            self.check_column(section, SITE_WIDTH, 0, kinds, false)?;
+            self.check_column(section, SITE_WIDTH, 1, ranges, true)?;
+            self.check_column(section, SITE_WIDTH, 2, string_count, true)?;
+        }
+        Ok(())
+    }
+
+    /// Number of rows of `width` numbers in a section
+    fn row_count(&self, section: Section, width: usize) -> usize {
+        self.section(section).len() / (4 * width)
+    }
+
+    /// Check that `offsets` has an entry for each of `owners` and one past
+    /// the last, which never decrease and end at `rows`
+    fn check_offsets(&self, offsets: Section, owners: usize, rows: usize) -> Result<()> {
+        if self.row_count(offsets, 1) != owners + 1 {
+            bail!("Corrupt {:?} section", offsets);
+        }
+        let mut previous = 0;
+        for i in 0..=owners {
+            let offset = self.get(offsets, i) as usize;
+            if offset < previous || offset > rows {
+                bail!("Corrupt {:?} section", offsets);
+            }
+            previous = offset;
+        }
+        if previous != rows {
+            bail!("Corrupt {:?} section", offsets);
+        }
+        Ok(())
+    }
+
+    /// Check that every number in a column is below `bound`, or is [`NONE`]
+    /// if the column is `optional`
+    fn check_column(
+        &self,
+        section: Section,
+        width: usize,
+        column: usize,
+        bound: usize,
+        optional: bool,
+    ) -> Result<()> {
+        for row in 0..self.row_count(section, width) {
+            let value = self.field(section, width, row, column);
+            if !(optional && value == NONE) && value as usize >= bound {
+                bail!("Corrupt {:?} section: row {} refers to {}", section, row, value);
+            }
+        }
+        Ok(())
+    }
+
+    /// Write an index of `symbols`
+    pub fn write(symbols: &SymbolMap, out: &mut dyn Write) -> Result<()> {
+        let mut ids: Vec<&SymbolId> = symbols.map.keys().collect();
+        for symbol in symbols.map.values() {
+            ids.extend(symbol.children.keys());
+            ids.extend(symbol.overrides.iter());
+        }
+        ids.extend(symbols.fields.values().flatten());
+        ids.sort();
+        ids.dedup();
+        let nodes: HashMap<&SymbolId, u32> = ids.iter().enumerate().map(|(n, id)| (*id, n as u32)).collect();
+
+        let mut writer = Writer::new();
+        let mut callers: Vec<Vec<[u32; CALLER_WIDTH]>> = vec![vec![]; ids.len()];
+        for (n, id) in ids.iter().enumerate() {
+            let symbol = symbols.map.get(*id);
+            let row = match symbol {
+                Some(symbol) => {
+                    let internal = if symbol.linkage == Linkage::Internal { FLAG_INTERNAL } else { 0 };
+                    [
+                        writer.string(id.as_str()),
+                        writer.string(&symbol.name),
+                        symbol.scope.as_deref().map_or(NONE, |scope| writer.string(scope)),
+                        FLAG_DEFINED | internal,
+                        writer.range(symbol.definition.as_ref()),
+                    ]
+                }
+                None => [writer.string(id.as_str()), NONE, NONE, 0, NONE],
+            };
+            writer.push(Section::Nodes, &row);
+
+            writer.start(Section::SymbolRangeOffsets, Section::SymbolRanges, 1);
+            writer.start(Section::EdgeOffsets, Section::Edges, EDGE_WIDTH);
+            writer.start(Section::OverrideOffsets, Section::Overrides, 1);
+            writer.start(Section::FieldCallOffsets, Section::FieldCalls, SITE_WIDTH);
+            let symbol = match symbol {
+                Some(symbol) => symbol,
+                None => continue,
+            };
+
+            for range in symbol.ranges.iter() {
+                let range = writer.range(Some(range));
+                writer.push(Section::SymbolRanges, &[range]);
+            }
+
+            let mut children: Vec<(u32, &Vec<Reference>)> = symbol
+                .children
+                .iter()
+                .map(|(child, references)| (nodes[child], references))
+                .collect();
+            children.sort_by_key(|(child, _)| *child);
+            for (child, references) in children {
+                let edge = (writer.sections[Section::Edges as usize].len() / EDGE_WIDTH) as u32;
+                let kinds: EdgeKinds = references.iter().map(|r| r.kind).collect();
+                writer.push(Section::Edges, &[child, kinds.bits() as u32]);
+                callers[child as usize].push([n as u32, edge]);
+
+                writer.start(Section::SiteOffsets, Section::Sites, SITE_WIDTH);
+                for reference in references.iter() {
+                    writer.reference(Section::Sites, reference);
+                }
+            }
+
+            for method in symbol.overrides.iter() {
+                writer.push(Section::Overrides, &[nodes[method]]);
+            }
+            for call in symbol.field_calls.iter() {
+                writer.reference(Section::FieldCalls, call);
+            }
+        }
+        writer.start(Section::SymbolRangeOffsets, Section::SymbolRanges, 1);
+        writer.start(Section::EdgeOffsets, Section::Edges, EDGE_WIDTH);
+        writer.start(Section::OverrideOffsets, Section::Overrides, 1);
+        writer.start(Section::FieldCallOffsets, Section::FieldCalls, SITE_WIDTH);
+        writer.start(Section::SiteOffsets, Section::Sites, SITE_WIDTH);
+
+        for rows in callers.iter() {
+            writer.start(Section::CallerOffsets, Section::Callers, CALLER_WIDTH);
+            for row in rows.iter() {
+                writer.push(Section::Callers, row);
+            }
+        }
+        writer.start(Section::CallerOffsets, Section::Callers, CALLER_WIDTH);
+
+        let mut classes: Vec<(&String, &Class)> = symbols.classes.iter().collect();
+        classes.sort_by_key(|(name, _)| *name);
+        for (name, class) in classes {
+            let name = writer.string(name);
+            writer.push(Section::Classes, &[name]);
+            writer.start(Section::BaseOffsets, Section::Bases, 1);
+            for base in class.bases.iter() {
+                let base = writer.string(base);
+                writer.push(Section::Bases, &[base]);
+            }
+        }
+        writer.start(Section::BaseOffsets, Section::Bases, 1);
+
+        let mut fields: Vec<(&String, &Vec<SymbolId>)> = symbols.fields.iter().collect();
+        fields.sort_by_key(|(field, _)| *field);
+        for (field, stored) in fields {
+            let field = writer.string(field);
+            writer.push(Section::Fields, &[field]);
+            writer.start(Section::StoreOffsets, Section::Stores, 1);
+            for function in stored.iter() {
+                writer.push(Section::Stores, &[nodes[function]]);
+            }
+        }
+        writer.start(Section::StoreOffsets, Section::Stores, 1);
+
+        writer.write(symbols.header.as_ref(), out)
+    }
+
+    pub fn header(&self) -> Option<&IndexHeader> {
+        self.header.as_ref()
+    }
+
+    fn section(&self, section: Section) -> &[u8] {
+        let (offset, len) = self.sections[section as usize];
+        &self.mmap[offset..offset + len]
+    }
+
+    fn get(&self, section: Section, i: usize) -> u32 {
+        let bytes = &self.section(section)[i * 4..i * 4 + 4];
+        u32::from_le_bytes(bytes.try_into().unwrap())
+    }
+
+    fn field(&self, section: Section, width: usize, row: usize, column: usize) -> u32 {
+        self.get(section, row * width + column)
+    }
+
+    /// Rows of `n` in the table indexed by `offsets`
+    fn rows(&self, offsets: Section, n: usize) -> Range<usize> {
+        self.get(offsets, n) as usize..self.get(offsets, n + 1) as usize
+    }
+
+    fn string(&self, number: u32) -> &str {
+        let bytes = &self.section(Section::Strings)[self.rows(Section::StringOffsets, number as usize)];
+        std::str::from_utf8(bytes).unwrap_or_default()
+    }
+
+    fn optional_string(&self, number: u32) -> Option<&str> {
+        (number != NONE).then(|| self.string(number))
+    }
+
+    fn range(&self, number: u32) -> Option<SourceRange> {
+        if number == NONE {
+            return None;
+        }
+        let field = |column| self.field(Section::Ranges, RANGE_WIDTH, number as usize, column);
+        let location = |column| SourceLocation {
+            file: self.string(field(column)).to_string(),
+            line: field(column + 1) as usize,
+            col: field(column + 2) as usize,
+        };
+        Some(SourceRange {
+            begin: location(0),
+            end: location(3),
+        })
+    }
+
+    fn reference(&self, section: Section, row: usize) -> Reference {
+        let field = |column| self.field(section, SITE_WIDTH, row, column);
+        Reference {
+            kind: EdgeKind::ALL[field(0) as usize],
+            range: self.range(field(1)),
+            via: self.optional_string(field(2)).map(str::to_string),
+        }
+    }
+
+    /// Number of symbols, including the ones only known as callees
+    pub fn len(&self) -> usize {
+        self.row_count(Section::Nodes, NODE_WIDTH)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Number of pairs of symbols with references from one to the other
+    pub fn edge_count(&self) -> usize {
+        self.row_count(Section::Edges, EDGE_WIDTH)
+    }
+
+    /// Number of the symbol with the given id
+    pub fn find(&self, id: &str) -> Option<u32> {
+        let (mut low, mut high) = (0, self.len());
+        while low < high {
+            let middle = (low + high) / 2;
+            match self.id(middle as u32).cmp(id) {
+                std::cmp::Ordering::Less => low = middle + 1,
+                std::cmp::Ordering::Greater => high = middle,
+                std::cmp::Ordering::Equal => return Some(middle as u32),
+            }
+        }
+        None
+    }
+
+    pub fn id(&self, node: u32) -> &str {
+        self.string(self.field(Section::Nodes, NODE_WIDTH, node as usize, 0))
+    }
+
+    /// Name of a symbol, `None` if it is not defined in the index
+    pub fn name(&self, node: u32) -> Option<&str> {
+        self.optional_string(self.field(Section::Nodes, NODE_WIDTH, node as usize, 1))
+    }
+
+    pub fn scope(&self, node: u32) -> Option<&str> {
+        self.optional_string(self.field(Section::Nodes, NODE_WIDTH, node as usize, 2))
+    }
+
+    pub fn is_defined(&self, node: u32) -> bool {
+        self.field(Section::Nodes, NODE_WIDTH, node as usize, 3) & FLAG_DEFINED != 0
+    }
+
+    pub fn is_internal(&self, node: u32) -> bool {
+        self.field(Section::Nodes, NODE_WIDTH, node as usize, 3) & FLAG_INTERNAL != 0
+    }
+
+    /// File a symbol is defined in, `None` if the index does not define it
+    pub fn definition_file(&self, node: u32) -> Option<&str> {
+        let range = self.field(Section::Nodes, NODE_WIDTH, node as usize, 4);
+        (range != NONE).then(|| self.string(self.field(Section::Ranges, RANGE_WIDTH, range as usize, 0)))
+    }
+
+    /// Symbols stored in any struct field, once per store
+    pub fn stored_functions(&self) -> impl Iterator<Item = u32> + '_ {
+        (0..self.section(Section::Stores).len() / 4).map(|store| self.get(Section::Stores, store))
+    }
+
+    /// Symbols referenced by `node`, with the kinds of the references
+    pub fn callees(&self, node: u32) -> impl Iterator<Item = (u32, EdgeKinds)> + '_ {
+        self.rows(Section::EdgeOffsets, node as usize).map(|edge| {
+            let field = |column| self.field(Section::Edges, EDGE_WIDTH, edge, column);
+            (field(0), EdgeKinds::from_bits(field(1) as u8))
+        })
+    }
+
+    /// Symbols referencing `node`, with the kinds of the references
+    pub fn callers(&self, node: u32) -> impl Iterator<Item = (u32, EdgeKinds)> + '_ {
+        self.rows(Section::CallerOffsets, node as usize).map(|row| {
+            let field = |column| self.field(Section::Callers, CALLER_WIDTH, row, column);
+            let kinds = self.field(Section::Edges, EDGE_WIDTH, field(1) as usize, 1);
+            (field(0), EdgeKinds::from_bits(kinds as u8))
+        })
+    }
+
+    /// Decode a symbol, `None` if it is not defined in the index
+    pub fn symbol(&self, node: u32) -> Option<Symbol> {
+        if !self.is_defined(node) {
+            return None;
+        }
+        let n = node as usize;
+        let flags = self.field(Section::Nodes, NODE_WIDTH, n, 3);
+
+        let ranges = self
+            .rows(Section::SymbolRangeOffsets, n)
+            .filter_map(|row| self.range(self.get(Section::SymbolRanges, row)))
+            .collect();
+        let children = self
+            .rows(Section::EdgeOffsets, n)
+            .map(|edge| {
+                let child = self.field(Section::Edges, EDGE_WIDTH, edge, 0);
+                let references = self
+                    .rows(Section::SiteOffsets, edge)
+                    .map(|row| self.reference(Section::Sites, row))
+                    .collect();
+                (SymbolId::new(self.id(child).to_string()), references)
+            })
+            .collect();
+        let overrides = self
+            .rows(Section::OverrideOffsets, n)
+            .map(|row| SymbolId::new(self.id(self.get(Section::Overrides, row)).to_string()))
+            .collect();
+        let field_calls = self
+            .rows(Section::FieldCallOffsets, n)
+            .map(|row| self.reference(Section::FieldCalls, row))
+            .collect();
+
+        Some(Symbol {
+            name: self.name(node)?.to_string(),
+            scope: self.scope(node).map(str::to_string),
+            linkage: if flags & FLAG_INTERNAL != 0 {
+                Linkage::Internal
+            } else {
+                Linkage::External
+            },
+            ranges,
+            definition: self.range(self.field(Section::Nodes, NODE_WIDTH, n, 4)),
+            children,
+            overrides,
+            field_calls,
+        })
+    }
+
+    /// Decode the whole index, such as to export it as JSON
+    pub fn to_symbol_map(&self) -> SymbolMap {
+        let mut symbols = SymbolMap::new();
+        symbols.header = self.header.clone();
+        for node in 0..self.len() as u32 {
+            if let Some(symbol) = self.symbol(node) {
+                symbols.map.insert(SymbolId::new(self.id(node).to_string()), symbol);
+            }
+        }
+
+        for row in 0..self.section(Section::Classes).len() / 4 {
+            let bases = self
+                .rows(Section::BaseOffsets, row)
+                .map(|base| self.string(self.get(Section::Bases, base)).to_string())
+                .collect();
+            let name = self.string(self.get(Section::Classes, row)).to_string();
+            symbols.classes.insert(name, Class { bases });
+        }
+
+        for row in 0..self.section(Section::Fields).len() / 4 {
+            let stored = self
+                .rows(Section::StoreOffsets, row)
+                .map(|store| SymbolId::new(self.id(self.get(Section::Stores, store)).to_string()))
+                .collect();
+            let field = self.string(self.get(Section::Fields, row)).to_string();
+            symbols.fields.insert(field, stored);
+        }
+        symbols
+    }
+}
@@ -1,6 +1,20 @@
+// Verbs are built through factories returning trait objects, and parse errors
+// are pest errors, which are large by design.
+#![allow(clippy::new_ret_no_self, clippy::result_large_err)]
+
+pub mod analysis;
 pub mod cfg;
+pub mod group;
+pub mod index;
+pub mod lsp;
+pub mod output;
 pub mod parser;
+pub mod query;
 pub mod scope;
+pub mod server;
+pub mod shell;
 pub mod symbols;
 pub mod verb;
 pub mod statement;
+
+pub use query::{Askl, QueryResult};
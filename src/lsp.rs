@@ -0,0 +1,411 @@
+use crate::symbols::{EdgeKind, Linkage, Reference, SourceLocation, SourceRange, Symbol, SymbolId, SymbolMap, Symbols};
+use anyhow::{anyhow, bail, Context, Result};
+use log::{debug, info};
+use lsp_types::notification::{DidOpenTextDocument, Exit, Initialized, Notification};
+use lsp_types::request::{
+    CallHierarchyIncomingCalls, CallHierarchyOutgoingCalls, CallHierarchyPrepare, Initialize, Request, Shutdown,
+    WorkspaceSymbol,
+};
+use lsp_types::{
+    CallHierarchyIncomingCallsParams, CallHierarchyItem, CallHierarchyOutgoingCallsParams, CallHierarchyPrepareParams,
+    DidOpenTextDocumentParams, InitializeResult, InitializedParams, Position, Range, SymbolKind, TextDocumentIdentifier, TextDocumentItem,
+    TextDocumentPositionParams, Url, WorkspaceSymbolParams,
+};
+use serde_json::{json, Value};
+use std::collections::{HashMap, VecDeque};
+use std::path::{Path, PathBuf};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+
+/// Read one message framed by a `Content-Length` header, `None` at the end of
+/// the stream
+pub async fn read_message<R: AsyncRead + Unpin>(reader: &mut BufReader<R>) -> Result<Option<Value>> {
+    let mut length = None;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).await? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("Content-Length") {
+                length = Some(value.trim().parse::<usize>()?);
+            }
+        }
+    }
+
+    let length = length.ok_or_else(|| anyhow!("Message without Content-Length"))?;
+    let mut content = vec![0; length];
+    reader.read_exact(&mut content).await?;
+    Ok(Some(serde_json::from_slice(&content)?))
+}
+
+/// Write one message with a `Content-Length` header
+pub async fn write_message<W: AsyncWrite + Unpin>(writer: &mut W, message: &Value) -> Result<()> {
+    let content = serde_json::to_vec(message)?;
+    writer
+        .write_all(format!("Content-Length: {}\r\n\r\n", content.len()).as_bytes())
+        .await?;
+    writer.write_all(&content).await?;
+    writer.flush().await?;
+    Ok(())
+}
+
+/// Client side of a language server connection
+///
+/// Requests are sent one at a time. Requests the server sends in the meantime
+/// are answered with an empty result, and its notifications are ignored.
+pub struct LspClient<R, W> {
+    reader: BufReader<R>,
+    writer: W,
+    next_id: i64,
+}
+
+impl<R: AsyncRead + Unpin, W: AsyncWrite + Unpin> LspClient<R, W> {
+    pub fn new(reader: R, writer: W) -> Self {
+        Self {
+            reader: BufReader::new(reader),
+            writer,
+            next_id: 0,
+        }
+    }
+
+    pub async fn request<Q: Request>(&mut self, params: Q::Params) -> Result<Q::Result> {
+        self.next_id += 1;
+        let id = self.next_id;
+        let request = json!({"jsonrpc": "2.0", "id": id, "method": Q::METHOD, "params": params});
+        write_message(&mut self.writer, &request).await?;
+
+        loop {
+            let message = read_message(&mut self.reader)
+                .await?
+                .ok_or_else(|| anyhow!("Language server closed the connection"))?;
+
+            match (message.get("id"), message.get("method")) {
+                (Some(server_id), Some(method)) => {
+                    debug!("Answering server request {}", method);
+                    let response = json!({"jsonrpc": "2.0", "id": server_id, "result": null});
+                    write_message(&mut self.writer, &response).await?;
+                }
+                (Some(response_id), None) if *response_id == json!(id) => {
+                    if let Some(error) = message.get("error") {
+                        bail!("{} failed: {}", Q::METHOD, error);
+                    }
+                    let result = message.get("result").cloned().unwrap_or(Value::Null);
+                    return serde_json::from_value(result)
+                        .with_context(|| format!("Unexpected response to {}", Q::METHOD));
+                }
+                _ => debug!("Ignoring message {}", message),
+            }
+        }
+    }
+
+    pub async fn notify<N: Notification>(&mut self, params: N::Params) -> Result<()> {
+        let notification = json!({"jsonrpc": "2.0", "method": N::METHOD, "params": params});
+        write_message(&mut self.writer, &notification).await
+    }
+
+    /// Initialize the server, returning what it reports about itself
+    pub async fn initialize(&mut self, root: &Path) -> Result<InitializeResult> {
+        let root_uri = Url::from_directory_path(root).map_err(|_| anyhow!("Invalid project root: {:?}", root))?;
+        let params = serde_json::from_value(json!({
+            "processId": std::process::id(),
+            "rootUri": root_uri,
+            "capabilities": {
+                "textDocument": {"callHierarchy": {}},
+                "workspace": {"symbol": {}},
+            },
+        }))?;
+        let result = self.request::<Initialize>(params).await?;
+        self.notify::<Initialized>(InitializedParams {}).await?;
+        Ok(result)
+    }
+
+    /// Make the server parse a file, so that its symbols get known
+    pub async fn open(&mut self, path: &Path, language: &str) -> Result<()> {
+        let uri = Url::from_file_path(path).map_err(|_| anyhow!("Invalid file path: {:?}", path))?;
+        let text = std::fs::read_to_string(path)?;
+        self.notify::<DidOpenTextDocument>(DidOpenTextDocumentParams {
+            text_document: TextDocumentItem {
+                uri,
+                language_id: language.to_string(),
+                version: 0,
+                text,
+            },
+        })
+        .await
+    }
+
+    pub async fn shutdown(&mut self) -> Result<()> {
+        self.request::<Shutdown>(()).await?;
+        self.notify::<Exit>(()).await
+    }
+}
+
+/// Call hierarchy items are the same function if they point at the same name
+type ItemKey = (Url, u32, u32);
+
+fn item_key(item: &CallHierarchyItem) -> ItemKey {
+    let start = item.selection_range.start;
+    (item.uri.clone(), start.line, start.character)
+}
+
+/// Builds a [`SymbolMap`] out of the call hierarchy reported by a language
+/// server
+pub struct LspIndexer {
+    root: PathBuf,
+    items: HashMap<ItemKey, CallHierarchyItem>,
+    /// Ranges of the calls from one item to another, in the document of the
+    /// caller
+    calls: HashMap<(ItemKey, ItemKey), Vec<Range>>,
+}
+
+impl LspIndexer {
+    pub fn new(root: &Path) -> Self {
+        Self {
+            root: root.to_path_buf(),
+            items: HashMap::new(),
+            calls: HashMap::new(),
+        }
+    }
+
+    /// Walk the call hierarchy in both directions, starting from every
+    /// function the server knows by `workspace/symbol`
+    pub async fn index<R, W>(mut self, client: &mut LspClient<R, W>) -> Result<SymbolMap>
+    where
+        R: AsyncRead + Unpin,
+        W: AsyncWrite + Unpin,
+    {
+        let symbols = client
+            .request::<WorkspaceSymbol>(WorkspaceSymbolParams {
+                query: String::new(),
+                ..Default::default()
+            })
+            .await?
+            .unwrap_or_default();
+        info!("Language server knows {} symbols", symbols.len());
+
+        let mut queue = VecDeque::new();
+        for symbol in symbols.into_iter() {
+            if !matches!(symbol.kind, SymbolKind::FUNCTION | SymbolKind::METHOD | SymbolKind::CONSTRUCTOR) {
+                continue;
+            }
+            let items = client
+                .request::<CallHierarchyPrepare>(CallHierarchyPrepareParams {
+                    text_document_position_params: TextDocumentPositionParams {
+                        text_document: TextDocumentIdentifier {
+                            uri: symbol.location.uri,
+                        },
+                        position: symbol.location.range.start,
+                    },
+                    work_done_progress_params: Default::default(),
+                })
+                .await?
+                .unwrap_or_default();
+            for item in items {
+                self.visit(item, &mut queue);
+            }
+        }
+
+        while let Some(key) = queue.pop_front() {
+            let item = self.items[&key].clone();
+
+            let outgoing = client
+                .request::<CallHierarchyOutgoingCalls>(CallHierarchyOutgoingCallsParams {
+                    item: item.clone(),
+                    work_done_progress_params: Default::default(),
+                    partial_result_params: Default::default(),
+                })
+                .await?
+                .unwrap_or_default();
+            for call in outgoing {
+                let callee = self.visit(call.to, &mut queue);
+                self.add_calls(key.clone(), callee, call.from_ranges);
+            }
+
+            let incoming = client
+                .request::<CallHierarchyIncomingCalls>(CallHierarchyIncomingCallsParams {
+                    item,
+                    work_done_progress_params: Default::default(),
+                    partial_result_params: Default::default(),
+                })
+                .await?
+                .unwrap_or_default();
+            for call in incoming {
+                let caller = self.visit(call.from, &mut queue);
+                self.add_calls(caller, key.clone(), call.from_ranges);
+            }
+        }
+
+        Ok(self.symbol_map())
+    }
+
+    fn visit(&mut self, item: CallHierarchyItem, queue: &mut VecDeque<ItemKey>) -> ItemKey {
+        let key = item_key(&item);
+        if !self.items.contains_key(&key) {
+            self.items.insert(key.clone(), item);
+            queue.push_back(key.clone());
+        }
+        key
+    }
+
+    fn add_calls(&mut self, caller: ItemKey, callee: ItemKey, ranges: Vec<Range>) {
+        self.calls.entry((caller, callee)).or_default().extend(ranges);
+    }
+
+    fn file(&self, uri: &Url) -> String {
+        match uri.to_file_path() {
+            Ok(path) => path
+                .strip_prefix(&self.root)
+                .unwrap_or(&path)
+                .to_string_lossy()
+                .into_owned(),
+            Err(_) => uri.to_string(),
+        }
+    }
+
+    fn in_project(&self, uri: &Url) -> bool {
+        uri.to_file_path().is_ok_and(|path| path.starts_with(&self.root))
+    }
+
+    fn location(&self, uri: &Url, position: Position) -> SourceLocation {
+        // Language servers count lines and columns from 0, clang from 1
+        SourceLocation {
+            file: self.file(uri),
+            line: position.line as usize + 1,
+            col: position.character as usize + 1,
+        }
+    }
+
+    fn range(&self, uri: &Url, range: Range) -> SourceRange {
+        SourceRange {
+            begin: self.location(uri, range.start),
+            end: self.location(uri, range.end),
+        }
+    }
+
+    /// Items standing for the same function, with the one to locate it at
+    /// first
+    ///
+    /// clangd identifies functions in the `data` of their items, which joins
+    /// a declaration with the definition in another file. Without it every
+    /// item is a function of its own. Definitions span their body, so the
+    /// item spanning the most lines is taken for one.
+    fn functions(&self) -> Vec<Vec<&ItemKey>> {
+        let mut by_data: HashMap<String, Vec<&ItemKey>> = HashMap::new();
+        let mut functions = vec![];
+        for (key, item) in self.items.iter() {
+            match &item.data {
+                Some(data) => by_data.entry(data.to_string()).or_default().push(key),
+                None => functions.push(vec![key]),
+            }
+        }
+        functions.extend(by_data.into_values());
+        for keys in functions.iter_mut() {
+            keys.sort_by_key(|key| {
+                let range = self.items[*key].range;
+                (std::cmp::Reverse(range.end.line - range.start.line), (*key).clone())
+            });
+        }
+        functions
+    }
+
+    /// Name of a function qualified by its scope, as clangd reports it in
+    /// the `detail` of an item
+    fn qualified_name(item: &CallHierarchyItem) -> &str {
+        match &item.detail {
+            Some(detail) if detail.ends_with(&format!("::{}", item.name)) => detail,
+            _ => &item.name,
+        }
+    }
+
+    /// Identify the items the way `gen-ast` identifies functions
+    ///
+    /// Language servers do not report linkage. Functions sharing a name in
+    /// different files are taken for static functions, which are then told
+    /// apart by their files. Methods and functions sharing a name within a
+    /// file are overloads instead, told apart by where they are located.
+    fn symbol_ids(&self) -> HashMap<&ItemKey, (SymbolId, Linkage)> {
+        let functions = self.functions();
+        let mut by_name: HashMap<&str, Vec<&Vec<&ItemKey>>> = HashMap::new();
+        for keys in functions.iter() {
+            by_name.entry(Self::qualified_name(&self.items[keys[0]])).or_default().push(keys);
+        }
+
+        let mut ids = HashMap::new();
+        for (name, functions) in by_name {
+            let mut files: Vec<&Url> = functions.iter().map(|keys| &keys[0].0).collect();
+            files.sort();
+            files.dedup();
+            let overloads = files.len() < functions.len()
+                || functions.iter().any(|keys| self.items[keys[0]].kind != SymbolKind::FUNCTION);
+            for keys in functions.iter() {
+                let id = if functions.len() == 1 {
+                    (SymbolId::new(name.to_string()), Linkage::External)
+                } else if overloads {
+                    let location = self.location(&keys[0].0, self.items[keys[0]].selection_range.start);
+                    (SymbolId::new(format!("{}@{}", name, location)), Linkage::External)
+                } else {
+                    let file = self.file(&keys[0].0);
+                    (SymbolId::new(format!("{}@{}", name, file)), Linkage::Internal)
+                };
+                for key in keys.iter() {
+                    ids.insert(*key, id.clone());
+                }
+            }
+        }
+        ids
+    }
+
+    fn symbol_map(&self) -> SymbolMap {
+        let ids = self.symbol_ids();
+
+        let mut children: HashMap<&SymbolId, HashMap<SymbolId, Vec<Reference>>> = HashMap::new();
+        for ((caller, callee), ranges) in self.calls.iter() {
+            let references = children
+                .entry(&ids[caller].0)
+                .or_default()
+                .entry(ids[callee].0.clone())
+                .or_default();
+            references.extend(ranges.iter().map(|range| Reference {
+                kind: EdgeKind::Call,
+                range: Some(self.range(&caller.0, *range)),
+                via: None,
+            }));
+            // Both ends of a call report it
+            references.sort();
+            references.dedup();
+        }
+
+        let mut symbol_map = SymbolMap::new();
+        for keys in self.functions() {
+            let (id, linkage) = &ids[keys[0]];
+            let item = &self.items[keys[0]];
+            let qualified_name = Self::qualified_name(item);
+            symbol_map.add(
+                id.clone(),
+                Symbol {
+                    name: item.name.clone(),
+                    scope: qualified_name
+                        .strip_suffix(&item.name)
+                        .and_then(|scope| scope.strip_suffix("::"))
+                        .map(String::from),
+                    linkage: *linkage,
+                    ranges: keys
+                        .iter()
+                        .map(|key| self.range(&key.0, self.items[*key].range))
+                        .collect(),
+                    // Functions outside the project, such as the ones of the C
+                    // library, are only declared as far as the index goes
+                    definition: self.in_project(&item.uri).then(|| self.range(&item.uri, item.range)),
+                    children: children.remove(id).unwrap_or_default(),
+                    overrides: vec![],
+                    field_calls: vec![],
+                },
+            );
+        }
+        symbol_map
+    }
+}
@@ -0,0 +1,395 @@
+use crate::cfg::EdgeKinds;
+use crate::query::{QueryResult, ResultEdge, ResultSymbol};
+use crate::symbols::{EdgeKind, Reference, SourceLocation, SymbolId};
+use anyhow::{bail, Result};
+use petgraph::dot::Dot;
+use petgraph::graphmap::DiGraphMap;
+use serde::{Serialize, Serializer};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::Write;
+use std::str::FromStr;
+
+/// Format of a query result
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Dot,
+    Json,
+    GraphMl,
+    Mermaid,
+    Csv,
+    Tree,
+}
+
+impl Format {
+    pub const NAMES: [&'static str; 6] = ["dot", "json", "graphml", "mermaid", "csv", "tree"];
+
+    /// Name the format is parsed from
+    pub fn name(self) -> &'static str {
+        match self {
+            Format::Dot => "dot",
+            Format::Json => "json",
+            Format::GraphMl => "graphml",
+            Format::Mermaid => "mermaid",
+            Format::Csv => "csv",
+            Format::Tree => "tree",
+        }
+    }
+}
+
+impl FromStr for Format {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "dot" => Format::Dot,
+            "json" => Format::Json,
+            "graphml" => Format::GraphMl,
+            "mermaid" => Format::Mermaid,
+            "csv" => Format::Csv,
+            "tree" => Format::Tree,
+            _ => bail!("Unknown format: {}, expected one of: {}", s, Format::NAMES.join(", ")),
+        })
+    }
+}
+
+/// Names of the symbols for display, extended by their location where several
+/// symbols share a name
+pub fn node_labels(result: &QueryResult) -> HashMap<&SymbolId, String> {
+    let mut counts: HashMap<&str, usize> = HashMap::new();
+    for symbol in result.symbols.iter() {
+        *counts.entry(&symbol.name).or_default() += 1;
+    }
+
+    result
+        .symbols
+        .iter()
+        .map(|symbol| {
+            let label = if counts[symbol.name.as_str()] > 1 {
+                let location = symbol
+                    .location
+                    .as_ref()
+                    .map_or_else(|| symbol.id.to_string(), |loc| loc.to_string());
+                format!("{} ({})", symbol.name, location)
+            } else {
+                symbol.name.clone()
+            };
+            (&symbol.id, label)
+        })
+        .collect()
+}
+
+fn name<'a>(result: &'a QueryResult, id: &'a SymbolId) -> &'a str {
+    result.get_symbol(id).map_or(id.as_str(), |s| s.name.as_str())
+}
+
+fn kind_names(kinds: EdgeKinds) -> Vec<&'static str> {
+    kinds.iter().map(|kind| kind.name()).collect()
+}
+
+fn site_label(site: &Reference) -> String {
+    let location = site
+        .range
+        .as_ref()
+        .map_or_else(|| "?".to_string(), |range| range.begin.to_string());
+    match (site.kind, &site.via) {
+        (EdgeKind::Call, None) => location,
+        (kind, None) => format!("{} ({})", location, kind.name()),
+        (kind, Some(via)) => format!("{} ({} via {})", location, kind.name(), via),
+    }
+}
+
+/// Write a query result in the given format
+pub fn write_graph(format: Format, result: &QueryResult, out: &mut dyn Write) -> Result<()> {
+    match format {
+        Format::Dot => write_dot(result, out),
+        Format::Json => write_json(result, out),
+        Format::GraphMl => write_graphml(result, out),
+        Format::Mermaid => write_mermaid(result, out),
+        Format::Csv => write_csv(result, out),
+        Format::Tree => write_tree(result, out),
+    }
+}
+
+/// Label of an edge: its weight in collapsed results, its sites otherwise
+fn edge_label(result: &QueryResult, edge: &ResultEdge) -> String {
+    if result.collapsed {
+        edge.weight.to_string()
+    } else {
+        edge.sites.iter().map(site_label).collect::<Vec<_>>().join(", ")
+    }
+}
+
+fn write_dot(result: &QueryResult, out: &mut dyn Write) -> Result<()> {
+    if result.symbols.iter().any(|symbol| symbol.group.is_some()) {
+        return write_dot_clusters(result, out);
+    }
+
+    let labels = node_labels(result);
+    let mut result_graph: DiGraphMap<&str, String> = DiGraphMap::new();
+    for edge in result.edges.iter() {
+        result_graph.add_edge(&labels[&edge.from], &labels[&edge.to], edge_label(result, edge));
+    }
+
+    for symbol in result.symbols.iter() {
+        result_graph.add_node(&labels[&symbol.id]);
+    }
+
+    write!(out, "{:?}", Dot::with_config(&result_graph, &[]))?;
+    Ok(())
+}
+
+/// DOT graph with a cluster for every group of symbols
+fn write_dot_clusters(result: &QueryResult, out: &mut dyn Write) -> Result<()> {
+    let labels = node_labels(result);
+    let nodes: HashMap<&SymbolId, usize> = result
+        .symbols
+        .iter()
+        .enumerate()
+        .map(|(i, symbol)| (&symbol.id, i))
+        .collect();
+    let mut groups: BTreeMap<Option<&str>, Vec<&ResultSymbol>> = BTreeMap::new();
+    for symbol in result.symbols.iter() {
+        groups.entry(symbol.group.as_deref()).or_default().push(symbol);
+    }
+
+    writeln!(out, "digraph {{")?;
+    for (i, (group, symbols)) in groups.iter().enumerate() {
+        let indent = if group.is_some() { "        " } else { "    " };
+        if let Some(group) = group {
+            writeln!(out, "    subgraph cluster_{} {{", i)?;
+            writeln!(out, "        label = {:?}", group)?;
+        }
+        for symbol in symbols {
+            writeln!(out, "{}{} [ label = {:?} ]", indent, nodes[&symbol.id], labels[&symbol.id])?;
+        }
+        if group.is_some() {
+            writeln!(out, "    }}")?;
+        }
+    }
+    for edge in result.edges.iter() {
+        writeln!(
+            out,
+            "    {} -> {} [ label = {:?} ]",
+            nodes[&edge.from],
+            nodes[&edge.to],
+            edge_label(result, edge)
+        )?;
+    }
+    writeln!(out, "}}")?;
+    Ok(())
+}
+
+#[derive(Serialize)]
+struct JsonNode<'a> {
+    id: &'a SymbolId,
+    name: &'a str,
+    location: Option<&'a SourceLocation>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    group: Option<&'a str>,
+}
+
+#[derive(Serialize)]
+struct JsonEdge<'a> {
+    from: &'a SymbolId,
+    to: &'a SymbolId,
+    kinds: Vec<&'static str>,
+    sites: &'a [Reference],
+    weight: usize,
+}
+
+#[derive(Serialize)]
+struct JsonGraph<'a> {
+    nodes: Vec<JsonNode<'a>>,
+    edges: Vec<JsonEdge<'a>>,
+}
+
+/// A query result serializes to the JSON written by [`Format::Json`]
+impl Serialize for QueryResult {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        JsonGraph {
+            nodes: self
+                .symbols
+                .iter()
+                .map(|symbol| JsonNode {
+                    id: &symbol.id,
+                    name: &symbol.name,
+                    location: symbol.location.as_ref(),
+                    group: symbol.group.as_deref(),
+                })
+                .collect(),
+            edges: self
+                .edges
+                .iter()
+                .map(|edge| JsonEdge {
+                    from: &edge.from,
+                    to: &edge.to,
+                    kinds: kind_names(edge.kinds),
+                    sites: &edge.sites,
+                    weight: edge.weight,
+                })
+                .collect(),
+        }
+        .serialize(serializer)
+    }
+}
+
+fn write_json(result: &QueryResult, out: &mut dyn Write) -> Result<()> {
+    serde_json::to_writer_pretty(&mut *out, result)?;
+    writeln!(out)?;
+    Ok(())
+}
+
+fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn write_graphml(result: &QueryResult, out: &mut dyn Write) -> Result<()> {
+    writeln!(out, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(out, r#"<graphml xmlns="http://graphml.graphdrawing.org/xmlns">"#)?;
+    writeln!(out, r#"  <key id="name" for="node" attr.name="name" attr.type="string"/>"#)?;
+    writeln!(out, r#"  <key id="location" for="node" attr.name="location" attr.type="string"/>"#)?;
+    writeln!(out, r#"  <key id="group" for="node" attr.name="group" attr.type="string"/>"#)?;
+    writeln!(out, r#"  <key id="kinds" for="edge" attr.name="kinds" attr.type="string"/>"#)?;
+    writeln!(out, r#"  <key id="sites" for="edge" attr.name="sites" attr.type="string"/>"#)?;
+    writeln!(out, r#"  <key id="weight" for="edge" attr.name="weight" attr.type="int"/>"#)?;
+    writeln!(out, r#"  <graph id="askl" edgedefault="directed">"#)?;
+    for symbol in result.symbols.iter() {
+        writeln!(out, r#"    <node id="{}">"#, xml_escape(symbol.id.as_str()))?;
+        writeln!(out, r#"      <data key="name">{}</data>"#, xml_escape(&symbol.name))?;
+        if let Some(location) = &symbol.location {
+            writeln!(out, r#"      <data key="location">{}</data>"#, xml_escape(&location.to_string()))?;
+        }
+        if let Some(group) = &symbol.group {
+            writeln!(out, r#"      <data key="group">{}</data>"#, xml_escape(group))?;
+        }
+        writeln!(out, "    </node>")?;
+    }
+    for edge in result.edges.iter() {
+        let sites = edge
+            .sites
+            .iter()
+            .map(site_label)
+            .collect::<Vec<_>>()
+            .join(", ");
+        writeln!(
+            out,
+            r#"    <edge source="{}" target="{}">"#,
+            xml_escape(edge.from.as_str()),
+            xml_escape(edge.to.as_str())
+        )?;
+        writeln!(out, r#"      <data key="kinds">{}</data>"#, kind_names(edge.kinds).join(","))?;
+        writeln!(out, r#"      <data key="sites">{}</data>"#, xml_escape(&sites))?;
+        writeln!(out, r#"      <data key="weight">{}</data>"#, edge.weight)?;
+        writeln!(out, "    </edge>")?;
+    }
+    writeln!(out, "  </graph>")?;
+    writeln!(out, "</graphml>")?;
+    Ok(())
+}
+
+fn write_mermaid(result: &QueryResult, out: &mut dyn Write) -> Result<()> {
+    let labels = node_labels(result);
+    let nodes: HashMap<&SymbolId, usize> = result
+        .symbols
+        .iter()
+        .enumerate()
+        .map(|(i, symbol)| (&symbol.id, i))
+        .collect();
+
+    writeln!(out, "flowchart LR")?;
+    for symbol in result.symbols.iter() {
+        writeln!(out, "    n{}[\"{}\"]", nodes[&symbol.id], labels[&symbol.id].replace('"', "#quot;"))?;
+    }
+    for edge in result.edges.iter() {
+        // References which are not calls are drawn dotted
+        let arrow = if edge.kinds.contains(EdgeKind::Call) {
+            "-->"
+        } else {
+            "-.->"
+        };
+        if result.collapsed {
+            writeln!(out, "    n{} {}|{}| n{}", nodes[&edge.from], arrow, edge.weight, nodes[&edge.to])?;
+        } else {
+            writeln!(out, "    n{} {} n{}", nodes[&edge.from], arrow, nodes[&edge.to])?;
+        }
+    }
+    Ok(())
+}
+
+fn csv_field(s: &str) -> String {
+    if s.contains([',', '"', '\n']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+fn write_csv(result: &QueryResult, out: &mut dyn Write) -> Result<()> {
+    writeln!(out, "caller_id,caller,callee_id,callee,kinds,sites")?;
+    for edge in result.edges.iter() {
+        let sites = edge
+            .sites
+            .iter()
+            .filter_map(|site| site.range.as_ref().map(|range| range.begin.to_string()))
+            .collect::<Vec<_>>()
+            .join(";");
+        writeln!(
+            out,
+            "{},{},{},{},{},{}",
+            csv_field(edge.from.as_str()),
+            csv_field(name(result, &edge.from)),
+            csv_field(edge.to.as_str()),
+            csv_field(name(result, &edge.to)),
+            kind_names(edge.kinds).join(";"),
+            csv_field(&sites),
+        )?;
+    }
+    Ok(())
+}
+
+/// Indented call tree, starting from the symbols nobody in the result calls
+///
+/// A symbol is expanded only once; later occurrences, including the ones
+/// closing a cycle, are marked with `...`.
+fn write_tree(result: &QueryResult, out: &mut dyn Write) -> Result<()> {
+    let labels = node_labels(result);
+    let mut callees: HashMap<&SymbolId, Vec<&SymbolId>> = HashMap::new();
+    let mut called: HashSet<&SymbolId> = HashSet::new();
+    for edge in result.edges.iter() {
+        callees.entry(&edge.from).or_default().push(&edge.to);
+        if edge.from != edge.to {
+            called.insert(&edge.to);
+        }
+    }
+
+    let mut expanded: HashSet<&SymbolId> = HashSet::new();
+    // Symbols left to write with their depth, as call chains may be deeper
+    // than the stack of the thread
+    let mut stack: Vec<(&SymbolId, usize)> = vec![];
+    let ids = result.symbols.iter().map(|symbol| &symbol.id);
+    let roots = ids.clone().filter(|id| !called.contains(id));
+    // Symbols only reachable through cycles have no root, so they start trees
+    // of their own once every root is done
+    for root in roots.chain(ids) {
+        if expanded.contains(root) {
+            continue;
+        }
+        stack.push((root, 0));
+        while let Some((id, depth)) = stack.pop() {
+            let indent = "  ".repeat(depth);
+            if !expanded.insert(id) {
+                writeln!(out, "{}{} ...", indent, labels[id])?;
+                continue;
+            }
+            writeln!(out, "{}{}", indent, labels[id])?;
+            // Pushed in reverse to be written in order
+            for callee in callees.get(id).into_iter().flatten().rev() {
+                stack.push((callee, depth + 1));
+            }
+        }
+    }
+    Ok(())
+}
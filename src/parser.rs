@@ -1,6 +1,6 @@
 use crate::{
     scope::{DefaultScope, Scope},
-    statement::build_statement,
+    statement::build_statements,
 };
 use anyhow::Result;
 use core::fmt::Debug;
@@ -49,7 +49,7 @@ impl NamedArgument {
         let value = Value::build(value).unwrap();
         Ok(NamedArgument {
             name: ident,
-            value: value,
+            value,
         })
     }
 }
@@ -60,11 +60,11 @@ pub fn parse(ask_code: &str) -> Result<Box<dyn Scope>> {
     let mut ast = vec![];
     for pair in pairs {
         match pair.as_rule() {
-            Rule::statement => ast.push(build_statement(pair)?),
+            Rule::statement => ast.push(pair),
             Rule::EOI => {}
             _ => unreachable!("Unknown rule: {:#?}", pair.as_rule()),
         };
     }
 
-    Ok(Box::new(DefaultScope::new(ast)))
+    Ok(Box::new(DefaultScope::new(build_statements(ast)?)))
 }
@@ -0,0 +1,205 @@
+use crate::cfg::{ControlFlowGraph, EdgeKinds, EdgeList};
+use crate::group::{self, Grouping};
+use crate::output::{write_graph, Format};
+use crate::parser::parse;
+use crate::scope::Scope;
+use crate::symbols::{Reference, SourceLocation, SymbolId};
+use anyhow::Result;
+use indicatif::ProgressBar;
+use std::collections::{BTreeMap, HashMap};
+use std::io::Write;
+
+/// A compiled query
+///
+/// ```no_run
+/// use askl::{cfg::ControlFlowGraph, symbols::SymbolMap, Askl};
+///
+/// let cfg = ControlFlowGraph::from_symbols(SymbolMap::load("symbol_map.idx")?);
+/// let result = Askl::new("\"main\" {}")?.run(&cfg);
+/// for symbol in result.symbols.iter() {
+///     println!("{}", symbol.name);
+/// }
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+#[derive(Debug)]
+pub struct Askl {
+    ast: Box<dyn Scope>,
+}
+
+impl Askl {
+    pub fn new(query: &str) -> Result<Self> {
+        Ok(Self { ast: parse(query)? })
+    }
+
+    /// Run the query against every symbol of the index, including those only
+    /// known as callees
+    pub fn run(&self, cfg: &ControlFlowGraph) -> QueryResult {
+        self.run_with_progress(cfg, None)
+    }
+
+    /// Same as [`Askl::run`], advancing `progress` for every symbol checked
+    pub fn run_with_progress(&self, cfg: &ControlFlowGraph, progress: Option<ProgressBar>) -> QueryResult {
+        let sources: Vec<SymbolId> = cfg.all_symbol_ids().cloned().collect();
+        let (symbols, edges) = self
+            .ast
+            .matched_symbols(cfg, &sources, progress)
+            .unwrap_or_else(|| (vec![], EdgeList(vec![])));
+        QueryResult::new(cfg, &symbols, &edges)
+    }
+}
+
+/// Symbol in a query result
+#[derive(Debug, Clone)]
+pub struct ResultSymbol {
+    pub id: SymbolId,
+    /// Qualified name
+    pub name: String,
+    pub location: Option<SourceLocation>,
+    /// Group the symbol belongs to, set by [`QueryResult::group`]
+    pub group: Option<String>,
+}
+
+/// Edge from a caller to a callee in a query result
+#[derive(Debug, Clone)]
+pub struct ResultEdge {
+    pub from: SymbolId,
+    pub to: SymbolId,
+    pub kinds: EdgeKinds,
+    pub sites: Vec<Reference>,
+    /// Number of references the edge stands for
+    pub weight: usize,
+}
+
+/// Graph of the symbols matched by a query and the edges between them
+#[derive(Debug, Clone, Default)]
+pub struct QueryResult {
+    /// Matched symbols and the endpoints of all edges, sorted by id
+    pub symbols: Vec<ResultSymbol>,
+    pub edges: Vec<ResultEdge>,
+    /// Symbols stand for groups of symbols, see [`QueryResult::collapse`]
+    pub collapsed: bool,
+}
+
+impl QueryResult {
+    /// Collect the details of `symbols` and `edges` from the index
+    pub fn new(cfg: &ControlFlowGraph, symbols: &[SymbolId], edges: &EdgeList) -> Self {
+        let mut ids: Vec<&SymbolId> = edges
+            .0
+            .iter()
+            .flat_map(|(from, to)| [from, to])
+            .chain(symbols.iter())
+            .collect();
+        ids.sort();
+        ids.dedup();
+
+        let symbols = ids
+            .into_iter()
+            .map(|id| {
+                let symbol = cfg.get_symbol(id);
+                ResultSymbol {
+                    id: id.clone(),
+                    name: symbol.map_or_else(|| id.to_string(), |s| s.qualified_name()),
+                    location: symbol.and_then(|s| s.location()).cloned(),
+                    group: None,
+                }
+            })
+            .collect();
+
+        let edges = edges
+            .0
+            .iter()
+            .map(|(from, to)| {
+                let sites = cfg.call_sites(from, to).to_vec();
+                ResultEdge {
+                    from: from.clone(),
+                    to: to.clone(),
+                    kinds: cfg.edge_kinds(from, to),
+                    weight: sites.len().max(1),
+                    sites,
+                }
+            })
+            .collect();
+
+        Self {
+            symbols,
+            edges,
+            collapsed: false,
+        }
+    }
+
+    /// Assign every symbol to the group of its location, symbols without one
+    /// go to [`group::EXTERNAL`]
+    pub fn group(&mut self, cfg: &ControlFlowGraph, grouping: &Grouping) {
+        for symbol in self.symbols.iter_mut() {
+            symbol.group = Some(match &symbol.location {
+                Some(location) => grouping.group(cfg.project_path(&location.file)),
+                None => group::EXTERNAL.to_string(),
+            });
+        }
+    }
+
+    /// Replace the symbols by their groups
+    ///
+    /// Edges between the same groups are merged, adding up their weights.
+    /// Edges within a group are dropped.
+    pub fn collapse(&self, cfg: &ControlFlowGraph, grouping: &Grouping) -> QueryResult {
+        let mut grouped = self.clone();
+        grouped.group(cfg, grouping);
+        let groups: HashMap<&SymbolId, &str> = grouped
+            .symbols
+            .iter()
+            .map(|symbol| (&symbol.id, symbol.group.as_deref().unwrap_or(group::EXTERNAL)))
+            .collect();
+
+        let mut edges: BTreeMap<(&str, &str), ResultEdge> = BTreeMap::new();
+        for edge in grouped.edges.iter() {
+            let (from, to) = (groups[&edge.from], groups[&edge.to]);
+            if from == to {
+                continue;
+            }
+            let merged = edges.entry((from, to)).or_insert_with(|| ResultEdge {
+                from: SymbolId::new(from.to_string()),
+                to: SymbolId::new(to.to_string()),
+                kinds: EdgeKinds::default(),
+                sites: vec![],
+                weight: 0,
+            });
+            for kind in edge.kinds.iter() {
+                merged.kinds.insert(kind);
+            }
+            merged.sites.extend(edge.sites.iter().cloned());
+            merged.weight += edge.weight;
+        }
+
+        let mut names: Vec<&str> = groups.values().copied().collect();
+        names.sort_unstable();
+        names.dedup();
+        let symbols = names
+            .into_iter()
+            .map(|name| ResultSymbol {
+                id: SymbolId::new(name.to_string()),
+                name: name.to_string(),
+                location: None,
+                group: None,
+            })
+            .collect();
+
+        QueryResult {
+            symbols,
+            edges: edges.into_values().collect(),
+            collapsed: true,
+        }
+    }
+
+    pub fn get_symbol(&self, id: &SymbolId) -> Option<&ResultSymbol> {
+        self.symbols
+            .binary_search_by(|symbol| symbol.id.cmp(id))
+            .ok()
+            .map(|i| &self.symbols[i])
+    }
+
+    /// Write the result in the given format
+    pub fn write(&self, format: Format, out: &mut dyn Write) -> Result<()> {
+        write_graph(format, self, out)
+    }
+}
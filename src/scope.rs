@@ -1,33 +1,134 @@
-use crate::cfg::{EdgeList, ControlFlowGraph};
+use crate::cfg::{ControlFlowGraph, Direction, EdgeKinds, EdgeList};
 use crate::parser::Rule;
-use crate::statement::{build_statement, Statement};
-use crate::symbols::SymbolId;
+use crate::statement::{build_statements, Statement};
+use crate::symbols::{EdgeKind, SymbolId};
 use core::fmt::Debug;
+use std::collections::{HashMap, HashSet, VecDeque};
 use pest::error::Error;
 use indicatif::ProgressBar;
 
 pub fn build_scope(pair: pest::iterators::Pair<Rule>) -> Result<Box<dyn Scope>, Error<Rule>> {
-    let statements: Result<Vec<Box<dyn Statement>>, _> =
-        pair.into_inner().map(build_statement).collect();
-    Ok(Box::new(DefaultScope(statements?)))
+    let mut direction = Direction::Callees;
+    let mut edge_kinds = EdgeKinds::all();
+    let mut statements = vec![];
+    let mut closure = None;
+    let mut negated = false;
+    for pair in pair.into_inner() {
+        match pair.as_rule() {
+            Rule::negation => negated = true,
+            Rule::callers => direction = Direction::Callers,
+            Rule::edge_kinds => edge_kinds = build_edge_kinds(pair)?,
+            Rule::statement => statements.push(pair),
+            Rule::closure => closure = Some(build_closure_depth(pair)?),
+            _ => unreachable!("Unknown rule: {:#?}", pair.as_rule()),
+        }
+    }
+
+    let statements = build_statements(statements)?;
+    let mut scope: Box<dyn Scope> = Box::new(DefaultScope::with_relation(statements, direction, edge_kinds));
+    if let Some(depth) = closure {
+        scope = Box::new(TransitiveScope::new(scope, depth));
+    }
+    if negated {
+        scope = Box::new(NegatedScope::new(scope));
+    }
+    Ok(scope)
+}
+
+/// Kinds listed in `[...]`, or all kinds if only exclusions like `!address`
+/// are listed
+fn build_edge_kinds(pair: pest::iterators::Pair<Rule>) -> Result<EdgeKinds, Error<Rule>> {
+    let mut included = EdgeKinds::NONE;
+    let mut excluded = EdgeKinds::NONE;
+    for pair in pair.into_inner() {
+        let (name, negated) = match pair.as_str().strip_prefix('!') {
+            Some(name) => (name, true),
+            None => (pair.as_str(), false),
+        };
+        let kind = EdgeKind::from_name(name).ok_or_else(|| {
+            Error::new_from_span(
+                pest::error::ErrorVariant::CustomError {
+                    message: format!(
+                        "Unknown edge kind: {}, expected one of: {}",
+                        name,
+                        EdgeKind::ALL.map(|kind| kind.name()).join(", ")
+                    ),
+                },
+                pair.as_span(),
+            )
+        })?;
+        if negated {
+            excluded.insert(kind);
+        } else {
+            included.insert(kind);
+        }
+    }
+
+    let mut kinds = if included == EdgeKinds::NONE {
+        EdgeKinds::all()
+    } else {
+        included
+    };
+    for kind in excluded.iter() {
+        kinds.remove(kind);
+    }
+    Ok(kinds)
+}
+
+fn build_closure_depth(pair: pest::iterators::Pair<Rule>) -> Result<Option<usize>, Error<Rule>> {
+    let depth = if let Some(depth) = pair.into_inner().next() {
+        depth
+    } else {
+        return Ok(None);
+    };
+
+    match depth.as_str().parse::<usize>() {
+        Ok(value) if value > 0 => Ok(Some(value)),
+        _ => Err(Error::new_from_span(
+            pest::error::ErrorVariant::CustomError {
+                message: "Closure depth must be a positive number".into(),
+            },
+            depth.as_span(),
+        )),
+    }
 }
 
 pub trait Scope: Debug {
     fn statements(&self) -> &Vec<Box<dyn Statement>>;
 
-    fn matched_symbols(&self, cfg: &ControlFlowGraph, symbols: &Vec<SymbolId>, progress: Option<ProgressBar>) -> Option<(Vec<SymbolId>, EdgeList)>{
+    /// Direction in which the symbols of the enclosing statement are related
+    /// to the symbols of this scope
+    fn direction(&self) -> Direction {
+        Direction::Callees
+    }
+
+    /// Kinds of edges relating the symbols of the enclosing statement to the
+    /// symbols of this scope
+    fn edge_kinds(&self) -> EdgeKinds {
+        EdgeKinds::all()
+    }
+
+    /// Match the scope against `symbols`
+    ///
+    /// `None` means that the scope does not match, so the enclosing statement
+    /// rejects its symbol. `Some` carries the matched symbols and the edges
+    /// leading to them; both may be empty when the scope matches without
+    /// contributing anything, as an empty or a negated scope does.
+    fn matched_symbols(&self, cfg: &ControlFlowGraph, symbols: &[SymbolId], progress: Option<ProgressBar>) -> Option<(Vec<SymbolId>, EdgeList)>{
         let mut result = EdgeList(vec![]);
         let mut result_sources : Vec<SymbolId> = vec![];
 
-        if self.statements().len() == 0 {
+        if self.statements().is_empty() {
             return Some((result_sources, result));
         }
-        
+
         // Iterate through all the statements in the scope or subscope of
         // the query
         for statement in self.statements().iter() {
 
             let statement_symbols = statement.verb().symbols(cfg, symbols);
+            let direction = statement.scope().direction();
+            let edge_kinds = statement.scope().edge_kinds();
 
             // Iterate through all the symbols in the CFG
             for symbol_id in statement_symbols.iter() {
@@ -35,22 +136,22 @@ pub trait Scope: Debug {
                     progress.inc(1);
                 }
 
-                let children = cfg.symbols.get_children(symbol_id);
+                let related = cfg.get_related(symbol_id, direction, edge_kinds);
 
                     // If the statement matches the symbol, add it to the result
-                if let Some((source_ids, mut edges)) = statement.scope().matched_symbols(cfg, &children, None) {
+                if let Some((source_ids, mut edges)) = statement.scope().matched_symbols(cfg, &related, None) {
                     for source_id in source_ids.into_iter() {
-                        edges.0.push((symbol_id.clone(), source_id.clone()));
+                        edges.0.push(direction.edge(symbol_id.clone(), source_id));
                     }
 
                     // This nodes matches the pattern, so remember it
                     result_sources.push(symbol_id.clone());
-                    result.0.extend(edges.0.into_iter()); 
+                    result.0.extend(edges.0);
                 }
             }
         }
 
-        if result_sources.len() == 0 {
+        if result_sources.is_empty() {
             return None;
         }
 
@@ -64,21 +165,41 @@ pub trait Scope: Debug {
 }
 
 #[derive(Debug)]
-pub struct DefaultScope(Vec<Box<dyn Statement>>);
+pub struct DefaultScope {
+    statements: Vec<Box<dyn Statement>>,
+    direction: Direction,
+    edge_kinds: EdgeKinds,
+}
 
 impl DefaultScope {
     pub fn new(statements: Vec<Box<dyn Statement>>) -> Self {
-        Self(statements)
+        Self::with_relation(statements, Direction::Callees, EdgeKinds::all())
+    }
+
+    pub fn with_relation(statements: Vec<Box<dyn Statement>>, direction: Direction, edge_kinds: EdgeKinds) -> Self {
+        Self {
+            statements,
+            direction,
+            edge_kinds,
+        }
     }
 }
 
 impl Scope for DefaultScope {
     fn statements(&self) -> &Vec<Box<dyn Statement>> {
-        &self.0
+        &self.statements
+    }
+
+    fn direction(&self) -> Direction {
+        self.direction
+    }
+
+    fn edge_kinds(&self) -> EdgeKinds {
+        self.edge_kinds
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Default)]
 pub struct EmptyScope(Vec<Box<dyn Statement>>);
 
 impl EmptyScope {
@@ -92,3 +213,142 @@ impl Scope for EmptyScope {
         &self.0
     }
 }
+
+/// Matches the symbols from which the wrapped scope can be reached through any
+/// number of intermediate calls, optionally limited to `depth` levels
+///
+/// The symbols handed to the scope form the first level. Every symbol is
+/// visited at most once, so recursive functions do not make the evaluation
+/// loop.
+#[derive(Debug)]
+pub struct TransitiveScope {
+    inner: Box<dyn Scope>,
+    depth: Option<usize>,
+}
+
+impl TransitiveScope {
+    pub fn new(inner: Box<dyn Scope>, depth: Option<usize>) -> Self {
+        Self { inner, depth }
+    }
+}
+
+impl Scope for TransitiveScope {
+    fn statements(&self) -> &Vec<Box<dyn Statement>> {
+        self.inner.statements()
+    }
+
+    fn direction(&self) -> Direction {
+        self.inner.direction()
+    }
+
+    fn edge_kinds(&self) -> EdgeKinds {
+        self.inner.edge_kinds()
+    }
+
+    fn matched_symbols(&self, cfg: &ControlFlowGraph, symbols: &[SymbolId], progress: Option<ProgressBar>) -> Option<(Vec<SymbolId>, EdgeList)> {
+        let direction = self.direction();
+        let edge_kinds = self.edge_kinds();
+
+        // Breadth-first walk over everything reachable within the depth limit,
+        // remembering each step taken
+        let mut levels: HashMap<SymbolId, usize> = HashMap::new();
+        let mut queue = VecDeque::new();
+        for symbol_id in symbols.iter() {
+            if levels.insert(symbol_id.clone(), 1).is_none() {
+                queue.push_back(symbol_id.clone());
+            }
+        }
+
+        let mut steps: Vec<(SymbolId, SymbolId)> = vec![];
+        while let Some(symbol_id) = queue.pop_front() {
+            let level = levels[&symbol_id];
+            if self.depth.is_some_and(|depth| level >= depth) {
+                continue;
+            }
+
+            for related in cfg.get_related(&symbol_id, direction, edge_kinds) {
+                if !levels.contains_key(&related) {
+                    levels.insert(related.clone(), level + 1);
+                    queue.push_back(related.clone());
+                }
+                steps.push((symbol_id.clone(), related));
+            }
+        }
+
+        let visited: Vec<SymbolId> = levels.into_keys().collect();
+        let (matched, mut result) = self.inner.matched_symbols(cfg, &visited, progress)?;
+
+        // Walk the steps backwards from the matched symbols to find every
+        // symbol that leads to one of them
+        let mut previous: HashMap<&SymbolId, Vec<&SymbolId>> = HashMap::new();
+        for (from, to) in steps.iter() {
+            previous.entry(to).or_default().push(from);
+        }
+
+        let mut alive: HashSet<&SymbolId> = matched.iter().collect();
+        let mut stack: Vec<&SymbolId> = matched.iter().collect();
+        while let Some(symbol_id) = stack.pop() {
+            for from in previous.get(symbol_id).into_iter().flatten() {
+                if alive.insert(from) {
+                    stack.push(from);
+                }
+            }
+        }
+
+        for (from, to) in steps.iter() {
+            if alive.contains(to) {
+                result.0.push(direction.edge(from.clone(), to.clone()));
+            }
+        }
+
+        let mut result_sources: Vec<SymbolId> = symbols
+            .iter()
+            .filter(|symbol_id| alive.contains(symbol_id))
+            .cloned()
+            .collect();
+
+        if result_sources.is_empty() {
+            return None;
+        }
+
+        result_sources.sort();
+        result_sources.dedup();
+        result.0.sort();
+        result.0.dedup();
+        Some((result_sources, result))
+    }
+}
+
+/// Matches exactly when the wrapped scope does not, without contributing any
+/// symbols or edges
+#[derive(Debug)]
+pub struct NegatedScope {
+    inner: Box<dyn Scope>,
+}
+
+impl NegatedScope {
+    pub fn new(inner: Box<dyn Scope>) -> Self {
+        Self { inner }
+    }
+}
+
+impl Scope for NegatedScope {
+    fn statements(&self) -> &Vec<Box<dyn Statement>> {
+        self.inner.statements()
+    }
+
+    fn direction(&self) -> Direction {
+        self.inner.direction()
+    }
+
+    fn edge_kinds(&self) -> EdgeKinds {
+        self.inner.edge_kinds()
+    }
+
+    fn matched_symbols(&self, cfg: &ControlFlowGraph, symbols: &[SymbolId], progress: Option<ProgressBar>) -> Option<(Vec<SymbolId>, EdgeList)> {
+        match self.inner.matched_symbols(cfg, symbols, progress) {
+            Some(_) => None,
+            None => Some((vec![], EdgeList(vec![]))),
+        }
+    }
+}
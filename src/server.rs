@@ -0,0 +1,251 @@
+use crate::cfg::{ControlFlowGraph, Direction, EdgeKinds, EdgeList};
+use crate::output::Format;
+use crate::symbols::{EdgeKind, Linkage, SourceRange, SymbolId};
+use crate::{Askl, QueryResult};
+use anyhow::Result;
+use jsonrpc_core::{Error, IoHandler, Params, Value};
+use serde::{Deserialize, Serialize};
+use std::io::{BufRead, Write};
+#[cfg(unix)]
+use std::io::BufReader;
+#[cfg(unix)]
+use std::os::unix::fs::FileTypeExt;
+#[cfg(unix)]
+use std::os::unix::net::UnixListener;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+/// Index served to the clients, replaced as a whole on reload
+struct State {
+    index: PathBuf,
+    cfg: ControlFlowGraph,
+}
+
+impl State {
+    fn load(index: PathBuf) -> Result<Self> {
+        let cfg = ControlFlowGraph::load(&index)?;
+        Ok(Self { index, cfg })
+    }
+}
+
+#[derive(Deserialize)]
+struct QueryParams {
+    query: String,
+    /// Results are JSON objects by default, other formats come as strings
+    format: Option<String>,
+}
+
+/// Symbols are looked up by their unique id or by their name, which may
+/// refer to several symbols
+#[derive(Deserialize)]
+struct SymbolParams {
+    id: Option<String>,
+    name: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct RelatedParams {
+    #[serde(flatten)]
+    symbol: SymbolParams,
+    /// Names of the edge kinds to follow, all kinds by default
+    kinds: Option<Vec<String>>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct ReloadParams {
+    /// Index to load instead of the current one
+    index: Option<String>,
+}
+
+#[derive(Serialize)]
+struct SymbolInfo<'a> {
+    id: &'a SymbolId,
+    name: &'a str,
+    linkage: Linkage,
+    ranges: &'a [SourceRange],
+}
+
+fn server_error<E: std::fmt::Display>(err: E) -> Error {
+    Error {
+        code: jsonrpc_core::ErrorCode::ServerError(-32000),
+        message: err.to_string(),
+        data: None,
+    }
+}
+
+fn to_value<T: Serialize>(value: &T) -> Result<Value, Error> {
+    serde_json::to_value(value).map_err(server_error)
+}
+
+impl SymbolParams {
+    fn resolve(&self, cfg: &ControlFlowGraph) -> Result<Vec<SymbolId>, Error> {
+        let ids = match (&self.id, &self.name) {
+            (Some(id), _) => {
+                let id = SymbolId::new(id.clone());
+                cfg.node_id(&id).map(|_| id).into_iter().collect()
+            }
+            (None, Some(name)) => cfg.find_symbols(name),
+            (None, None) => return Err(Error::invalid_params("Either id or name is required")),
+        };
+        if ids.is_empty() {
+            return Err(server_error(format!(
+                "Unknown symbol: {}",
+                self.id.as_ref().or(self.name.as_ref()).unwrap()
+            )));
+        }
+        Ok(ids)
+    }
+}
+
+fn parse_kinds(kinds: &Option<Vec<String>>) -> Result<EdgeKinds, Error> {
+    match kinds {
+        Some(kinds) => kinds
+            .iter()
+            .map(|name| {
+                EdgeKind::from_name(name)
+                    .ok_or_else(|| Error::invalid_params(format!("Unknown edge kind: {}", name)))
+            })
+            .collect(),
+        None => Ok(EdgeKinds::all()),
+    }
+}
+
+fn query(state: &State, params: QueryParams) -> Result<Value, Error> {
+    let result = Askl::new(&params.query)
+        .map_err(|err| Error::invalid_params(err.to_string()))?
+        .run(&state.cfg);
+    match params.format.as_deref() {
+        None | Some("json") => to_value(&result),
+        Some(format) => {
+            let format: Format = format
+                .parse()
+                .map_err(|err: anyhow::Error| Error::invalid_params(err.to_string()))?;
+            let mut out = vec![];
+            result.write(format, &mut out).map_err(server_error)?;
+            Ok(Value::String(String::from_utf8_lossy(&out).into_owned()))
+        }
+    }
+}
+
+fn symbol(state: &State, params: SymbolParams) -> Result<Value, Error> {
+    let cfg = &state.cfg;
+    let ids = params.resolve(cfg)?;
+    let symbols: Vec<SymbolInfo> = ids
+        .iter()
+        .filter_map(|id| cfg.get_symbol(id).map(|symbol| (id, symbol)))
+        .map(|(id, symbol)| SymbolInfo {
+            id,
+            name: &symbol.name,
+            linkage: symbol.linkage,
+            ranges: &symbol.ranges,
+        })
+        .collect();
+    to_value(&symbols)
+}
+
+/// The symbols with their callers or callees and the edges between them
+fn related(state: &State, params: RelatedParams, direction: Direction) -> Result<Value, Error> {
+    let cfg = &state.cfg;
+    let ids = params.symbol.resolve(cfg)?;
+    let kinds = parse_kinds(&params.kinds)?;
+
+    let mut edges = vec![];
+    for id in ids.iter() {
+        for related in cfg.get_related(id, direction, kinds) {
+            edges.push(direction.edge(id.clone(), related));
+        }
+    }
+    edges.sort();
+    edges.dedup();
+    to_value(&QueryResult::new(cfg, &ids, &EdgeList(edges)))
+}
+
+fn reload(state: &mut State, params: ReloadParams) -> Result<Value, Error> {
+    let index = params.index.map_or_else(|| state.index.clone(), PathBuf::from);
+    *state = State::load(index).map_err(server_error)?;
+    Ok(serde_json::json!({ "symbols": state.cfg.symbol_ids().count() }))
+}
+
+/// Parameters of a method call, with missing parameters treated as an empty
+/// object
+fn parse_params<D: serde::de::DeserializeOwned>(params: Params) -> Result<D, Error> {
+    match params {
+        Params::None => Params::Map(Default::default()).parse(),
+        params => params.parse(),
+    }
+}
+
+/// JSON-RPC handler answering queries against the index at `index`
+///
+/// Methods:
+/// - `query {query, format?}`: run a query
+/// - `symbol {id | name}`: describe symbols
+/// - `callers {id | name, kinds?}`, `callees {id | name, kinds?}`: direct
+///   callers or callees of symbols
+/// - `reload {index?}`: load the index again, or another index
+pub fn io_handler(index: &Path) -> Result<IoHandler> {
+    let state = Arc::new(RwLock::new(State::load(index.to_path_buf())?));
+    let mut io = IoHandler::new();
+
+    let s = state.clone();
+    io.add_sync_method("query", move |params: Params| {
+        query(&s.read().unwrap(), parse_params(params)?)
+    });
+    let s = state.clone();
+    io.add_sync_method("symbol", move |params: Params| {
+        symbol(&s.read().unwrap(), parse_params(params)?)
+    });
+    let s = state.clone();
+    io.add_sync_method("callers", move |params: Params| {
+        related(&s.read().unwrap(), parse_params(params)?, Direction::Callers)
+    });
+    let s = state.clone();
+    io.add_sync_method("callees", move |params: Params| {
+        related(&s.read().unwrap(), parse_params(params)?, Direction::Callees)
+    });
+    io.add_sync_method("reload", move |params: Params| {
+        reload(&mut state.write().unwrap(), parse_params(params)?)
+    });
+    Ok(io)
+}
+
+/// Answer requests read from `input`, one per line, until it is closed
+pub fn serve<R: BufRead, W: Write>(io: &IoHandler, input: R, mut output: W) -> Result<()> {
+    for line in input.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        if let Some(response) = io.handle_request_sync(&line) {
+            writeln!(output, "{}", response)?;
+            output.flush()?;
+        }
+    }
+    Ok(())
+}
+
+/// Answer requests from every client connecting to a Unix socket at `path`
+#[cfg(unix)]
+pub fn serve_socket(io: IoHandler, path: &Path) -> Result<()> {
+    // A socket left behind by a previous server would make binding fail
+    if std::fs::metadata(path).is_ok_and(|meta| meta.file_type().is_socket()) {
+        std::fs::remove_file(path)?;
+    }
+    let listener = UnixListener::bind(path)?;
+    let io = Arc::new(io);
+    for stream in listener.incoming() {
+        let stream = stream?;
+        let io = io.clone();
+        std::thread::spawn(move || {
+            let result = stream
+                .try_clone()
+                .map_err(anyhow::Error::from)
+                .and_then(|input| serve(&io, BufReader::new(input), stream));
+            if let Err(err) = result {
+                log::warn!("Client failed: {}", err);
+            }
+        });
+    }
+    Ok(())
+}
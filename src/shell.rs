@@ -0,0 +1,58 @@
+//! Line editing support for interactive shells: completion of symbol names
+//! and detection of queries continuing on the next line
+
+/// Whether all braces and quotes of a query are closed, so that a shell can
+/// run it rather than read another line
+///
+/// Too many closing braces are left for the parser to report.
+pub fn is_complete(query: &str) -> bool {
+    let mut depth = 0i32;
+    let mut quoted = false;
+    for c in query.chars() {
+        match c {
+            '"' => quoted = !quoted,
+            '{' if !quoted => depth += 1,
+            '}' if !quoted => depth -= 1,
+            _ => {}
+        }
+    }
+    depth <= 0 && !quoted
+}
+
+/// Symbol names to complete, plain and qualified
+#[derive(Debug, Default)]
+pub struct Names(Vec<String>);
+
+impl Names {
+    pub fn new(mut names: Vec<String>) -> Self {
+        names.sort();
+        names.dedup();
+        Self(names)
+    }
+
+    /// Position of the name typed before `pos` in `line`, and the names it
+    /// may be completed to
+    pub fn complete(&self, line: &str, pos: usize) -> (usize, Vec<String>) {
+        let line = &line[..pos];
+        // Inside a string everything up to the opening quote belongs to the
+        // name, elsewhere only identifier characters and scope separators do
+        let start = if line.matches('"').count() % 2 == 1 {
+            line.rfind('"').map_or(0, |i| i + 1)
+        } else {
+            line.rfind(|c: char| !(c.is_alphanumeric() || c == '_' || c == ':'))
+                .map_or(0, |i| i + 1)
+        };
+        let prefix = &line[start..];
+        if prefix.is_empty() {
+            return (pos, vec![]);
+        }
+
+        let first = self.0.partition_point(|name| name.as_str() < prefix);
+        let candidates = self.0[first..]
+            .iter()
+            .take_while(|name| name.starts_with(prefix))
+            .cloned()
+            .collect();
+        (start, candidates)
+    }
+}
@@ -10,7 +10,7 @@ pub struct DefaultStatement {
     pub scope: Box<dyn Scope>,
 }
 
-pub fn build_statement<'a>(
+pub fn build_statement(
     pair: pest::iterators::Pair<Rule>,
 ) -> Result<Box<dyn Statement>, Error<Rule>> {
     let mut verb: Box<dyn Verb> = UnitVerb::new();
@@ -35,11 +35,30 @@ pub fn build_statement<'a>(
     }
 
     Ok(Box::new(DefaultStatement {
-        verb: verb,
-        scope: scope,
+        verb,
+        scope,
     }))
 }
 
+/// Build the statements of a block, leaving out the empty ones that line
+/// breaks leave behind, as those would match every symbol. A block made only
+/// of empty statements keeps one, so that `{}` still matches everything.
+pub fn build_statements(
+    pairs: Vec<pest::iterators::Pair<Rule>>,
+) -> Result<Vec<Box<dyn Statement>>, Error<Rule>> {
+    let first = pairs.first().cloned();
+    let mut statements = vec![];
+    for pair in pairs {
+        if pair.clone().into_inner().next().is_some() {
+            statements.push(build_statement(pair)?);
+        }
+    }
+    if let (true, Some(pair)) = (statements.is_empty(), first) {
+        statements.push(build_statement(pair)?);
+    }
+    Ok(statements)
+}
+
 pub trait Statement: Debug {
     fn verb(&self) -> &dyn Verb;
     fn scope(&self) -> &dyn Scope;
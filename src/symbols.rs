@@ -1,32 +1,207 @@
-use clang_ast::SourceRange;
+use crate::index::Index;
+use anyhow::{anyhow, bail, Context};
+use log::warn;
 use serde::{Deserialize, Serialize};
-use std::collections::HashSet;
-use std::collections::hash_map::DefaultHasher;
-use std::{collections::HashMap, hash, hash::Hasher};
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use std::{collections::HashMap, collections::HashSet, fmt};
 
+/// Version of the layout of [`SymbolMap`], raised whenever indexes written
+/// before can no longer be read
+pub const SCHEMA_VERSION: u32 = 2;
+
+const REGENERATE: &str = "regenerate it with gen-ast or lsp-index";
+
+/// SHA-256 digest of the inputs a file is generated from
 #[derive(Debug, PartialEq, Eq, PartialOrd, Hash, Ord, Copy, Clone, Serialize, Deserialize)]
-pub struct FileHash(u64);
+pub struct FileHash([u8; 32]);
 
 impl FileHash {
-    pub fn new<T: hash::Hash>(url: &T) -> Self {
-        let mut s = DefaultHasher::new();
-        url.hash(&mut s);
-        FileHash(s.finish())
+    /// Digest of `parts`, each prefixed by its length, so that moving bytes
+    /// from one part to the next changes the digest
+    pub fn new<'a>(parts: impl IntoIterator<Item = &'a [u8]>) -> Self {
+        let mut hasher = Sha256::new();
+        for part in parts {
+            hasher.update((part.len() as u64).to_le_bytes());
+            hasher.update(part);
+        }
+        FileHash(hasher.finalize().into())
+    }
+}
+
+/// Position in a source file
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct SourceLocation {
+    pub file: String,
+    pub line: usize,
+    pub col: usize,
+}
+
+impl SourceLocation {
+    /// Clang locations inside macro expansions point into the macro
+    /// definition, so the expansion site is preferred
+    pub fn from_clang(loc: &clang_ast::SourceLocation) -> Option<Self> {
+        let loc = loc.expansion_loc.as_ref().or(loc.spelling_loc.as_ref())?;
+        Some(Self {
+            file: loc.file.to_string(),
+            line: loc.line,
+            col: loc.col,
+        })
+    }
+}
+
+impl fmt::Display for SourceLocation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.file, self.line)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct SourceRange {
+    pub begin: SourceLocation,
+    pub end: SourceLocation,
+}
+
+impl SourceRange {
+    pub fn from_clang(range: &clang_ast::SourceRange) -> Option<Self> {
+        Some(Self {
+            begin: SourceLocation::from_clang(&range.begin)?,
+            end: SourceLocation::from_clang(&range.end)?,
+        })
+    }
+}
+
+/// How a symbol refers to another symbol
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum EdgeKind {
+    /// Direct call
+    Call,
+    /// Address taken outside of a call, e.g. stored in a variable
+    AddressTaken,
+    /// Passed as an argument to a call, typically as a callback
+    CallbackArgument,
+    /// Possible target of a virtual call, an override of the called method
+    Virtual,
+    /// Possible target of a call through a function pointer in a struct
+    /// field, any function stored into that field
+    Indirect,
+}
+
+impl EdgeKind {
+    pub const ALL: [EdgeKind; 5] = [
+        EdgeKind::Call,
+        EdgeKind::AddressTaken,
+        EdgeKind::CallbackArgument,
+        EdgeKind::Virtual,
+        EdgeKind::Indirect,
+    ];
+
+    /// Name of the kind in queries
+    pub fn name(&self) -> &'static str {
+        match self {
+            EdgeKind::Call => "call",
+            EdgeKind::AddressTaken => "address",
+            EdgeKind::CallbackArgument => "callback",
+            EdgeKind::Virtual => "virtual",
+            EdgeKind::Indirect => "indirect",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|kind| kind.name() == name)
     }
 }
 
+/// Single reference from one symbol to another
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Reference {
+    pub kind: EdgeKind,
+    pub range: Option<SourceRange>,
+    /// Struct field called through, such as `file_ops.open`, or class of the
+    /// object a virtual method is called on, such as `ns::Base`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub via: Option<String>,
+}
+
+/// Whether a symbol is visible outside of its translation unit
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Linkage {
+    #[default]
+    External,
+    /// Declared `static`, so symbols with the same name in other files are
+    /// different symbols
+    Internal,
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Symbol {
+    /// Name for display, which is not necessarily unique
     pub name: String,
+    /// Enclosing namespaces and classes, such as `ns::Class`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scope: Option<String>,
+    #[serde(default)]
+    pub linkage: Linkage,
     pub ranges: Vec<SourceRange>,
-    pub children: HashSet<SymbolId>,
+    /// Range of the declaration with the body, one of `ranges`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub definition: Option<SourceRange>,
+    /// Referenced symbols with the references to each of them
+    pub children: HashMap<SymbolId, Vec<Reference>>,
+    /// Virtual methods this method overrides
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub overrides: Vec<SymbolId>,
+    /// Calls through struct fields, whose targets are only known once all
+    /// stores into the fields are known
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub field_calls: Vec<Reference>,
+}
+
+impl Symbol {
+    /// Position of the definition, or of the first known declaration of a
+    /// symbol only declared
+    pub fn location(&self) -> Option<&SourceLocation> {
+        self.definition.as_ref().or(self.ranges.first()).map(|range| &range.begin)
+    }
+
+    /// Name prefixed by the enclosing scopes, such as `ns::Class::method`
+    pub fn qualified_name(&self) -> String {
+        match &self.scope {
+            Some(scope) => format!("{}::{}", scope, self.name),
+            None => self.name.clone(),
+        }
+    }
+
+    /// Whether the symbol is called `name`, qualified or not
+    pub fn has_name(&self, name: &str) -> bool {
+        name_matches(&self.name, self.scope.as_deref(), name)
+    }
+}
+
+/// Whether a symbol named `symbol_name` in `scope` is called `name`,
+/// qualified or not
+pub fn name_matches(symbol_name: &str, scope: Option<&str>, name: &str) -> bool {
+    if symbol_name == name {
+        return true;
+    }
+    match (scope, name.strip_suffix(symbol_name)) {
+        (Some(scope), Some(prefix)) => prefix.strip_suffix("::") == Some(scope),
+        _ => false,
+    }
 }
 
 pub trait Symbols: ToString {
     fn add(&mut self, id: SymbolId, symbol: Symbol);
-    fn into_vec(&self) -> Vec<SymbolId>;
+    fn to_vec(&self) -> Vec<SymbolId>;
 }
 
+/// Unique identity of a symbol
+///
+/// Symbols with external linkage are identified by their mangled name, and
+/// symbols with internal linkage additionally by the file they are declared in.
 #[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq, Hash, PartialOrd, Ord)]
 pub struct SymbolId(String);
 
@@ -34,59 +209,392 @@ impl SymbolId {
     pub fn new(id: String) -> Self {
         Self(id)
     }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for SymbolId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// C++ class, as far as needed to follow virtual calls
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+pub struct Class {
+    /// Qualified names of the direct base classes
+    pub bases: Vec<String>,
+}
+
+/// Where, when and by what an index was generated
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct IndexHeader {
+    pub schema_version: u32,
+    /// Tool which wrote the index and its version, such as `gen-ast 0.1.0`
+    pub generator: String,
+    /// Version of the compiler or language server that parsed the sources
+    pub clang_version: Option<String>,
+    pub compile_commands: Option<PathBuf>,
+    pub project_root: Option<PathBuf>,
+    /// Commit checked out in the project root
+    pub vcs_revision: Option<String>,
+    /// Seconds since the Unix epoch
+    pub created: Option<u64>,
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone)]
+impl IndexHeader {
+    /// Header for an index of the project at `project_root`, written now
+    pub fn new(generator: &str, project_root: &Path) -> Self {
+        let vcs_revision = std::process::Command::new("git")
+            .arg("-C")
+            .arg(project_root)
+            .args(["rev-parse", "HEAD"])
+            .output()
+            .ok()
+            .filter(|output| output.status.success())
+            .and_then(|output| String::from_utf8(output.stdout).ok())
+            .map(|revision| revision.trim().to_string());
+        Self {
+            schema_version: SCHEMA_VERSION,
+            generator: format!("{} {}", generator, env!("CARGO_PKG_VERSION")),
+            clang_version: None,
+            compile_commands: None,
+            project_root: Some(project_root.to_path_buf()),
+            vcs_revision,
+            created: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .ok()
+                .map(|created| created.as_secs()),
+        }
+    }
+
+    /// Fail with a hint on what to do if this version of askl cannot read
+    /// the index
+    pub fn check(&self) -> anyhow::Result<()> {
+        if self.schema_version < SCHEMA_VERSION {
+            bail!(
+                "The index was written by {} with schema version {}, but askl {} reads version {}; {}",
+                self.generator,
+                self.schema_version,
+                env!("CARGO_PKG_VERSION"),
+                SCHEMA_VERSION,
+                REGENERATE
+            );
+        }
+        if self.schema_version > SCHEMA_VERSION {
+            bail!(
+                "The index was written by {} with schema version {}, but askl {} only reads version {}; upgrade askl or {}",
+                self.generator,
+                self.schema_version,
+                env!("CARGO_PKG_VERSION"),
+                SCHEMA_VERSION,
+                REGENERATE
+            );
+        }
+        Ok(())
+    }
+
+    /// Check the header of an index read from `path`, which indexes written
+    /// before headers were introduced lack
+    pub fn check_optional(header: Option<&IndexHeader>, path: &Path) -> anyhow::Result<()> {
+        match header {
+            Some(header) => header.check().with_context(|| format!("Cannot read index {:?}", path)),
+            None => {
+                warn!("Index {:?} has no header, {} to keep reading it", path, REGENERATE);
+                Ok(())
+            }
+        }
+    }
+}
+
+/// The header alone, to explain why an index failed to load
+#[derive(Deserialize)]
+struct HeaderOnly {
+    header: Option<IndexHeader>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
 pub struct SymbolMap {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub header: Option<IndexHeader>,
     pub map: HashMap<SymbolId, Symbol>,
+    /// Class hierarchy by qualified class name
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub classes: HashMap<String, Class>,
+    /// Functions stored into struct fields, by field name such as
+    /// `file_ops.open`
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub fields: HashMap<String, Vec<SymbolId>>,
 }
 
 impl SymbolMap {
     pub fn new() -> Self {
         Self {
+            header: None,
             map: HashMap::new(),
+            classes: HashMap::new(),
+            fields: HashMap::new(),
+        }
+    }
+
+    pub fn add_field_stores(&mut self, field: String, mut functions: Vec<SymbolId>) {
+        let existing = self.fields.entry(field).or_default();
+        existing.append(&mut functions);
+        existing.sort();
+        existing.dedup();
+    }
+
+    /// Add an `Indirect` reference to every function stored into a struct
+    /// field, at the sites of the calls through that field
+    pub fn resolve_field_calls(&mut self) {
+        for symbol in self.map.values_mut() {
+            for call in symbol.field_calls.iter() {
+                let targets = call.via.as_ref().and_then(|field| self.fields.get(field));
+                for target in targets.into_iter().flatten() {
+                    let references = symbol.children.entry(target.clone()).or_default();
+                    references.push(call.clone());
+                    references.sort();
+                    references.dedup();
+                }
+            }
         }
     }
 
+    pub fn add_class(&mut self, name: String, mut class: Class) {
+        let existing = self.classes.entry(name).or_default();
+        existing.bases.append(&mut class.bases);
+        existing.bases.sort();
+        existing.bases.dedup();
+    }
+
+    /// Whether `class` is `base` or derives from it, as far as the classes in
+    /// the index tell
+    fn derives_from(&self, class: &str, base: &str) -> bool {
+        let mut seen = HashSet::from([class]);
+        let mut queue = vec![class];
+        while let Some(class) = queue.pop() {
+            if class == base {
+                return true;
+            }
+            for parent in self.classes.get(class).into_iter().flat_map(|class| class.bases.iter()) {
+                if seen.insert(parent) {
+                    queue.push(parent);
+                }
+            }
+        }
+        false
+    }
+
+    /// Add a `Virtual` reference to every override of a called method, at
+    /// the sites of the calls dispatched at run time
+    ///
+    /// Those are the calls `gen-ast` marks by the class of their object.
+    /// Overrides are followed transitively, so a call through the root of a
+    /// hierarchy reaches the methods of all derived classes in the index, but
+    /// only of those deriving from the class of the object.
+    pub fn resolve_virtual_calls(&mut self) {
+        let mut overridden_by: HashMap<&SymbolId, Vec<&SymbolId>> = HashMap::new();
+        for (id, symbol) in self.map.iter() {
+            for method in symbol.overrides.iter() {
+                overridden_by.entry(method).or_default().push(id);
+            }
+        }
+        if overridden_by.is_empty() {
+            return;
+        }
+
+        let mut added: Vec<(SymbolId, SymbolId, Vec<Reference>)> = vec![];
+        for (caller, symbol) in self.map.iter() {
+            for (callee, references) in symbol.children.iter() {
+                let sites: Vec<Reference> = references
+                    .iter()
+                    .filter(|reference| reference.kind == EdgeKind::Call && reference.via.is_some())
+                    .map(|reference| Reference {
+                        kind: EdgeKind::Virtual,
+                        ..reference.clone()
+                    })
+                    .collect();
+                if sites.is_empty() {
+                    continue;
+                }
+
+                let mut seen = HashSet::from([callee]);
+                let mut queue = vec![callee];
+                while let Some(method) = queue.pop() {
+                    for target in overridden_by.get(method).into_iter().flatten() {
+                        if !seen.insert(*target) {
+                            continue;
+                        }
+                        queue.push(*target);
+                        // Methods outside of classes would be malformed
+                        // input, which is not narrowed down
+                        let class = self.map.get(*target).and_then(|target| target.scope.as_deref());
+                        let reaching: Vec<Reference> = sites
+                            .iter()
+                            .filter(|site| match (class, &site.via) {
+                                (Some(class), Some(object)) => self.derives_from(class, object),
+                                _ => true,
+                            })
+                            .cloned()
+                            .collect();
+                        if !reaching.is_empty() {
+                            added.push((caller.clone(), (*target).clone(), reaching));
+                        }
+                    }
+                }
+            }
+        }
+
+        for (caller, target, mut sites) in added {
+            let references = self
+                .map
+                .get_mut(&caller)
+                .unwrap()
+                .children
+                .entry(target)
+                .or_default();
+            references.append(&mut sites);
+            references.sort();
+            references.dedup();
+        }
+    }
+
+    /// Read an index written by `gen-ast`, binary or JSON
+    pub fn load<P: AsRef<Path>>(path: P) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        if Index::is_index(path)? {
+            return Ok(Index::open(path)?.to_symbol_map());
+        }
+
+        let json = std::fs::read(path)?;
+        match serde_json::from_slice::<SymbolMap>(&json) {
+            Ok(symbols) => {
+                IndexHeader::check_optional(symbols.header.as_ref(), path)?;
+                Ok(symbols)
+            }
+            Err(err) => {
+                // A header of another version explains the failure better
+                if let Ok(HeaderOnly { header: Some(header) }) = serde_json::from_slice(&json) {
+                    header.check().with_context(|| format!("Cannot read index {:?}", path))?;
+                }
+                Err(anyhow!(
+                    "Cannot read index {:?}: {}; it may come from another version of askl, {}",
+                    path,
+                    err,
+                    REGENERATE
+                ))
+            }
+        }
+    }
+
+    /// Write the index as JSON if the path ends in `.json`, and in the binary
+    /// format otherwise
+    ///
+    /// The index is written to a temporary file next to `path`, which is then
+    /// renamed over it, so an index being read, or mapped, never changes.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> anyhow::Result<()> {
+        let path = path.as_ref();
+        let mut temporary = path
+            .file_name()
+            .ok_or_else(|| anyhow!("Cannot write index to {:?}", path))?
+            .to_os_string();
+        temporary.push(format!(".{}.tmp", std::process::id()));
+        let temporary = path.with_file_name(temporary);
+
+        let result = self.write(path, &temporary).and_then(|()| Ok(std::fs::rename(&temporary, path)?));
+        if result.is_err() {
+            let _ = std::fs::remove_file(&temporary);
+        }
+        result.with_context(|| format!("Cannot write index {:?}", path))
+    }
+
+    /// Write the index to `file`, in the format `path` asks for
+    fn write(&self, path: &Path, file: &Path) -> anyhow::Result<()> {
+        let mut out = BufWriter::new(File::create(file)?);
+        if path.extension().is_some_and(|extension| extension == "json") {
+            serde_json::to_writer_pretty(&mut out, self)?;
+        } else {
+            Index::write(self, &mut out)?;
+        }
+        out.into_inner()?.sync_all()?;
+        Ok(())
+    }
+
+    /// Add the symbols of another translation unit, joining the ones both
+    /// know like [`Symbols::add`]
     pub fn merge(&mut self, other: SymbolMap) -> &mut Self {
-        self.map.extend(other.map);
+        for (id, symbol) in other.map {
+            self.add(id, symbol);
+        }
+        for (name, class) in other.classes {
+            self.add_class(name, class);
+        }
+        for (field, functions) in other.fields {
+            self.add_field_stores(field, functions);
+        }
         self
     }
 
-    pub fn iter<'a>(&'a self) -> impl Iterator<Item = (&SymbolId, &Symbol)> + 'a {
+    pub fn iter(&self) -> impl Iterator<Item = (&SymbolId, &Symbol)> {
         self.map.iter()
     }
 
     pub fn get_children(&self, symbol_id: &SymbolId) -> Vec<SymbolId> {
-        let symbol = if let Some(symbol) = self.map.get(&symbol_id) {
+        let symbol = if let Some(symbol) = self.map.get(symbol_id) {
             symbol
         } else {
             return vec![];
         };
 
-        symbol.children.clone().into_iter().collect::<Vec<_>>()    
+        symbol.children.keys().cloned().collect::<Vec<_>>()    
     }
 }
 
 impl Symbols for SymbolMap {
     fn add(&mut self, id: SymbolId, mut symbol: Symbol) {
         if let Some(existing) = self.map.get_mut(&id) {
-            assert_eq!(existing.name, symbol.name);
+            if existing.name != symbol.name {
+                warn!(
+                    "Symbol {} is named both {} and {}, keeping the former",
+                    id, existing.name, symbol.name
+                );
+            }
+            if existing.scope.is_none() {
+                existing.scope = symbol.scope;
+            }
+            if existing.definition.is_none() {
+                existing.definition = symbol.definition;
+            }
             existing.ranges.append(&mut symbol.ranges);
-            existing.children.extend(symbol.children);
+            existing.ranges.sort();
+            existing.ranges.dedup();
+            existing.overrides.append(&mut symbol.overrides);
+            existing.overrides.sort();
+            existing.overrides.dedup();
+            existing.field_calls.append(&mut symbol.field_calls);
+            existing.field_calls.sort();
+            existing.field_calls.dedup();
+            for (child, mut references) in symbol.children {
+                let existing_references = existing.children.entry(child).or_default();
+                existing_references.append(&mut references);
+                existing_references.sort();
+                existing_references.dedup();
+            }
         } else {
             self.map.insert(id, symbol);
         }
     }
 
-    fn into_vec(&self) -> Vec<SymbolId> {
-        self.map.iter().map(|(k, _)| k.clone()).collect::<Vec<_>>()
+    fn to_vec(&self) -> Vec<SymbolId> {
+        self.map.keys().cloned().collect::<Vec<_>>()
     }
 }
 
-impl ToString for SymbolMap {
-    fn to_string(&self) -> String {
-        serde_json::to_string_pretty(&self.map.clone().into_values().collect::<Vec<Symbol>>())
-            .unwrap()
+impl fmt::Display for SymbolMap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let symbols = self.map.values().collect::<Vec<&Symbol>>();
+        write!(f, "{}", serde_json::to_string_pretty(&symbols).map_err(|_| fmt::Error)?)
     }
 }
@@ -1,11 +1,15 @@
-use crate::cfg::ControlFlowGraph;
+use crate::analysis::{cycles, dead_symbols, EntryPoints};
+use crate::cfg::{ControlFlowGraph, Direction, EdgeKinds};
 use crate::parser::{Identifier, NamedArgument, Rule};
 use crate::symbols::SymbolId;
 use anyhow::{anyhow, bail, Result};
 use log::debug;
 use core::fmt::Debug;
+use glob::{MatchOptions, Pattern};
 use pest::error::Error;
-use std::collections::HashMap;
+use regex::{Regex, RegexBuilder};
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
 
 fn build_generic_verb(prev_verb: Box<dyn Verb>, pair: pest::iterators::Pair<Rule>) -> Result<Box<dyn Verb>> {
     let mut pair = pair.into_inner();
@@ -20,10 +24,17 @@ fn build_generic_verb(prev_verb: Box<dyn Verb>, pair: pest::iterators::Pair<Rule
         named.insert(arg.name.0, arg.value.0);
     }
 
-    let span = ident.as_span();
     match Identifier::build(ident)?.0.as_str() {
         FilterVerb::NAME => FilterVerb::new(prev_verb, positional, named),
+        ExcludeVerb::NAME | ExcludeVerb::ALIAS => ExcludeVerb::new(prev_verb, positional, named),
         AllVerb::NAME => Ok(AllVerb::new()),
+        LocationVerb::FILE => LocationVerb::new(prev_verb, Location::File, named),
+        LocationVerb::DIR => LocationVerb::new(prev_verb, Location::Dir, named),
+        DeadVerb::NAME => DeadVerb::new(prev_verb, named),
+        RecursiveVerb::NAME | RecursiveVerb::ALIAS => RecursiveVerb::new(prev_verb, named),
+        name @ (DegreeVerb::LEAF | DegreeVerb::ROOT | DegreeVerb::FANIN | DegreeVerb::FANOUT) => {
+            DegreeVerb::new(prev_verb, name, named)
+        }
         unknown => Err(anyhow!("Unknown filter: {}", unknown)),
     }
 }
@@ -56,41 +67,365 @@ pub fn build_verb(prev_verb: Box<dyn Verb>, pair: pest::iterators::Pair<Rule>) -
             })
         });
 
-    return verb;
+    verb
 }
 
 pub trait Verb: Debug {
-    fn symbols(&self, cfg: &ControlFlowGraph, symbols: &Vec<SymbolId>) -> Vec<SymbolId>;
+    fn symbols(&self, cfg: &ControlFlowGraph, symbols: &[SymbolId]) -> Vec<SymbolId>;
+}
+
+fn parse_flag(named: &HashMap<String, String>, flag: &str) -> Result<bool> {
+    match named.get(flag).map(String::as_str) {
+        None | Some("false") => Ok(false),
+        Some("true") => Ok(true),
+        Some(other) => bail!("{} must be \"true\" or \"false\", got \"{}\"", flag, other),
+    }
+}
+
+/// Symbol name pattern, compiled once when the verb is built
+#[derive(Debug)]
+enum NameMatcher {
+    Exact { name: String, ignore_case: bool },
+    Regex(Regex),
+    Glob { pattern: Pattern, options: MatchOptions },
+}
+
+impl NameMatcher {
+    fn new(named: &HashMap<String, String>) -> Result<Self> {
+        let ignore_case = parse_flag(named, "ignore_case")?;
+        let matcher = match (named.get("name"), named.get("regex"), named.get("glob")) {
+            (Some(name), None, None) => NameMatcher::Exact {
+                name: name.clone(),
+                ignore_case,
+            },
+            (None, Some(regex), None) => NameMatcher::Regex(
+                RegexBuilder::new(regex)
+                    .case_insensitive(ignore_case)
+                    .build()
+                    .map_err(|e| anyhow!("Invalid regex \"{}\": {}", regex, e))?,
+            ),
+            (None, None, Some(glob)) => NameMatcher::Glob {
+                pattern: Pattern::new(glob)
+                    .map_err(|e| anyhow!("Invalid glob \"{}\": {}", glob, e))?,
+                options: MatchOptions {
+                    case_sensitive: !ignore_case,
+                    ..MatchOptions::new()
+                },
+            },
+            _ => bail!("Must contain exactly one of name, regex or glob fields"),
+        };
+        Ok(matcher)
+    }
+
+    fn matches(&self, name: &str) -> bool {
+        match self {
+            NameMatcher::Exact { name: expected, ignore_case: false } => expected == name,
+            NameMatcher::Exact { name: expected, ignore_case: true } => {
+                expected.to_lowercase() == name.to_lowercase()
+            }
+            NameMatcher::Regex(regex) => regex.is_match(name),
+            NameMatcher::Glob { pattern, options } => pattern.matches_with(name, *options),
+        }
+    }
+
+    /// Match either the plain or the qualified name of a symbol, symbols
+    /// not defined in the index, such as library functions only known as
+    /// callees, are matched by their id
+    fn matches_symbol(&self, cfg: &ControlFlowGraph, symbol_id: &SymbolId) -> bool {
+        match cfg.symbol_name(symbol_id) {
+            Some((name, None)) => self.matches(name),
+            Some((name, Some(scope))) => self.matches(name) || self.matches(&format!("{}::{}", scope, name)),
+            None => cfg.node_id(symbol_id).is_some() && self.matches(symbol_id.as_str()),
+        }
+    }
 }
 
 #[derive(Debug)]
 struct FilterVerb {
     prev: Box<dyn Verb>,
-    name: String,
+    matcher: NameMatcher,
 }
 
 impl FilterVerb {
     const NAME: &'static str = "filter";
 
     fn new(prev_verb: Box<dyn Verb>, _positional: Vec<String>, named: HashMap<String, String>) -> Result<Box<dyn Verb>> {
-        if let Some(name) = named.get("name") {
-            Ok(Box::new(Self {
-                prev: prev_verb,
-                name: name.clone()
-            }))
-        } else {
-            bail!("Must contain name field");
-        }
+        Ok(Box::new(Self {
+            prev: prev_verb,
+            matcher: NameMatcher::new(&named)?,
+        }))
     }
 }
 
 impl Verb for FilterVerb {
-    fn symbols(&self, cfg: &ControlFlowGraph, symbols: &Vec<SymbolId>) -> Vec<SymbolId> {
+    fn symbols(&self, cfg: &ControlFlowGraph, symbols: &[SymbolId]) -> Vec<SymbolId> {
+        self.prev
+            .symbols(cfg, symbols)
+            .into_iter()
+            .filter(|s| {
+                self.matcher.matches_symbol(cfg, s)
+            })
+            .collect()
+    }
+}
+
+/// Drops the symbols whose name matches, keeping everything else
+#[derive(Debug)]
+struct ExcludeVerb {
+    prev: Box<dyn Verb>,
+    matcher: NameMatcher,
+}
+
+impl ExcludeVerb {
+    const NAME: &'static str = "exclude";
+    const ALIAS: &'static str = "not";
+
+    fn new(prev_verb: Box<dyn Verb>, _positional: Vec<String>, named: HashMap<String, String>) -> Result<Box<dyn Verb>> {
+        Ok(Box::new(Self {
+            prev: prev_verb,
+            matcher: NameMatcher::new(&named)?,
+        }))
+    }
+}
+
+impl Verb for ExcludeVerb {
+    fn symbols(&self, cfg: &ControlFlowGraph, symbols: &[SymbolId]) -> Vec<SymbolId> {
         self.prev
             .symbols(cfg, symbols)
             .into_iter()
             .filter(|s| {
-                self.name == cfg.get_symbol(s).unwrap().name
+                !self.matcher.matches_symbol(cfg, s)
+            })
+            .collect()
+    }
+}
+
+/// Which part of a path a [`LocationVerb`] matches
+#[derive(Debug, Clone, Copy)]
+enum Location {
+    /// The file itself
+    File,
+    /// Any directory containing the file
+    Dir,
+}
+
+/// Keeps the symbols defined in matching files or directories, symbols only
+/// declared are matched by their first declaration
+///
+/// Paths are taken relative to the project root recorded in the index, and
+/// `*` does not match across directories, while `**` does.
+#[derive(Debug)]
+struct LocationVerb {
+    prev: Box<dyn Verb>,
+    location: Location,
+    pattern: Pattern,
+    options: MatchOptions,
+}
+
+impl LocationVerb {
+    const FILE: &'static str = "file";
+    const DIR: &'static str = "dir";
+
+    fn new(prev_verb: Box<dyn Verb>, location: Location, named: HashMap<String, String>) -> Result<Box<dyn Verb>> {
+        let path = match (named.get("path"), named.len()) {
+            (Some(path), 1) => path,
+            _ => bail!("Must contain exactly the path field"),
+        };
+        let path = path.strip_prefix("./").unwrap_or(path).trim_end_matches('/');
+        Ok(Box::new(Self {
+            prev: prev_verb,
+            location,
+            pattern: Pattern::new(path).map_err(|e| anyhow!("Invalid path \"{}\": {}", path, e))?,
+            options: MatchOptions {
+                require_literal_separator: true,
+                ..MatchOptions::new()
+            },
+        }))
+    }
+
+    fn matches_file(&self, file: &Path) -> bool {
+        match self.location {
+            Location::File => self.pattern.matches_path_with(file, self.options),
+            Location::Dir => file
+                .ancestors()
+                .skip(1)
+                .any(|dir| self.pattern.matches_path_with(dir, self.options)),
+        }
+    }
+}
+
+impl Verb for LocationVerb {
+    fn symbols(&self, cfg: &ControlFlowGraph, symbols: &[SymbolId]) -> Vec<SymbolId> {
+        self.prev
+            .symbols(cfg, symbols)
+            .into_iter()
+            .filter(|s| {
+                cfg.get_symbol(s)
+                    .and_then(|symbol| symbol.location())
+                    .is_some_and(|location| self.matches_file(cfg.project_path(&location.file)))
+            })
+            .collect()
+    }
+}
+
+/// Keeps the symbols unreachable from the entry points
+///
+/// The entry point is `main` unless others are given by `entry`, `regex` or
+/// `exported`.
+#[derive(Debug)]
+struct DeadVerb {
+    prev: Box<dyn Verb>,
+    entries: EntryPoints,
+}
+
+impl DeadVerb {
+    const NAME: &'static str = "dead";
+
+    fn new(prev_verb: Box<dyn Verb>, named: HashMap<String, String>) -> Result<Box<dyn Verb>> {
+        let mut entries = EntryPoints {
+            exported: parse_flag(&named, "exported")?,
+            strict: parse_flag(&named, "strict")?,
+            ..Default::default()
+        };
+        for (name, value) in named.iter() {
+            match name.as_str() {
+                "entry" => entries.names.push(value.clone()),
+                "regex" => entries
+                    .patterns
+                    .push(Regex::new(value).map_err(|e| anyhow!("Invalid regex \"{}\": {}", value, e))?),
+                "exported" | "strict" => {}
+                unknown => bail!("Unknown field: {}", unknown),
+            }
+        }
+        if entries.is_empty() {
+            entries.names.push("main".to_string());
+        }
+        Ok(Box::new(Self {
+            prev: prev_verb,
+            entries,
+        }))
+    }
+}
+
+impl Verb for DeadVerb {
+    fn symbols(&self, cfg: &ControlFlowGraph, symbols: &[SymbolId]) -> Vec<SymbolId> {
+        let dead = dead_symbols(cfg, &self.entries);
+        self.prev
+            .symbols(cfg, symbols)
+            .into_iter()
+            .filter(|s| dead.binary_search(s).is_ok())
+            .collect()
+    }
+}
+
+/// Keeps the symbols calling themselves, directly or through other symbols
+///
+/// Only calls are followed unless other kinds are given by `kinds`.
+#[derive(Debug)]
+struct RecursiveVerb {
+    prev: Box<dyn Verb>,
+    kinds: EdgeKinds,
+}
+
+impl RecursiveVerb {
+    const NAME: &'static str = "recursive";
+    const ALIAS: &'static str = "scc";
+
+    fn new(prev_verb: Box<dyn Verb>, named: HashMap<String, String>) -> Result<Box<dyn Verb>> {
+        let kinds = match (named.get("kinds"), named.len()) {
+            (None, 0) => EdgeKinds::calls(),
+            (Some(kinds), 1) => kinds.parse()?,
+            _ => bail!("Must contain at most the kinds field"),
+        };
+        Ok(Box::new(Self {
+            prev: prev_verb,
+            kinds,
+        }))
+    }
+}
+
+impl Verb for RecursiveVerb {
+    fn symbols(&self, cfg: &ControlFlowGraph, symbols: &[SymbolId]) -> Vec<SymbolId> {
+        let cycles = cycles(cfg, self.kinds);
+        let recursive: HashSet<&SymbolId> = cycles.iter().flat_map(|cycle| cycle.symbols.iter()).collect();
+        self.prev
+            .symbols(cfg, symbols)
+            .into_iter()
+            .filter(|s| recursive.contains(s))
+            .collect()
+    }
+}
+
+/// Keeps the symbols related to a number of other symbols within bounds
+///
+/// References of any kind count unless `kinds` is given, and references of a
+/// symbol to itself do not count.
+#[derive(Debug)]
+struct DegreeVerb {
+    prev: Box<dyn Verb>,
+    direction: Direction,
+    kinds: EdgeKinds,
+    min: usize,
+    max: usize,
+}
+
+impl DegreeVerb {
+    const LEAF: &'static str = "leaf";
+    const ROOT: &'static str = "root";
+    const FANIN: &'static str = "fanin";
+    const FANOUT: &'static str = "fanout";
+
+    fn new(prev_verb: Box<dyn Verb>, name: &str, named: HashMap<String, String>) -> Result<Box<dyn Verb>> {
+        let bound = |field: &str| -> Result<Option<usize>> {
+            named
+                .get(field)
+                .map(|value| {
+                    value
+                        .parse()
+                        .map_err(|_| anyhow!("{} must be a non-negative number, got \"{}\"", field, value))
+                })
+                .transpose()
+        };
+        let (direction, min, max) = match name {
+            Self::LEAF => (Direction::Callees, None, Some(0)),
+            Self::ROOT => (Direction::Callers, None, Some(0)),
+            Self::FANIN => (Direction::Callers, bound("min")?, bound("max")?),
+            _ => (Direction::Callees, bound("min")?, bound("max")?),
+        };
+        if matches!(name, Self::FANIN | Self::FANOUT) && min.is_none() && max.is_none() {
+            bail!("Must contain min or max fields");
+        }
+        if let (Some(min), Some(max)) = (min, max) {
+            if min > max {
+                bail!("min must not exceed max, got {} and {}", min, max);
+            }
+        }
+        for field in named.keys() {
+            match field.as_str() {
+                "kinds" => {}
+                "min" | "max" if matches!(name, Self::FANIN | Self::FANOUT) => {}
+                unknown => bail!("Unknown field: {}", unknown),
+            }
+        }
+        Ok(Box::new(Self {
+            prev: prev_verb,
+            direction,
+            kinds: named.get("kinds").map_or(Ok(EdgeKinds::all()), |kinds| kinds.parse())?,
+            min: min.unwrap_or(0),
+            max: max.unwrap_or(usize::MAX),
+        }))
+    }
+}
+
+impl Verb for DegreeVerb {
+    fn symbols(&self, cfg: &ControlFlowGraph, symbols: &[SymbolId]) -> Vec<SymbolId> {
+        self.prev
+            .symbols(cfg, symbols)
+            .into_iter()
+            .filter(|s| {
+                let related = cfg.get_related(s, self.direction, self.kinds);
+                let degree = related.iter().filter(|other| *other != s).count();
+                (self.min..=self.max).contains(&degree)
             })
             .collect()
     }
@@ -109,11 +444,8 @@ impl AllVerb {
 }
 
 impl Verb for AllVerb {
-    fn symbols(&self, cfg: &ControlFlowGraph, _symbols: &Vec<SymbolId>) -> Vec<SymbolId> {
-        cfg
-            .iter_symbols()
-            .map(|(id, _)| id.clone())
-            .collect()
+    fn symbols(&self, cfg: &ControlFlowGraph, _symbols: &[SymbolId]) -> Vec<SymbolId> {
+        cfg.symbol_ids().cloned().collect()
     }
 }
 
@@ -128,7 +460,7 @@ impl UnitVerb {
 }
 
 impl Verb for UnitVerb {
-    fn symbols(&self, _cfg: &ControlFlowGraph, symbols: &Vec<SymbolId>) -> Vec<SymbolId> {
-        symbols.clone()
+    fn symbols(&self, _cfg: &ControlFlowGraph, symbols: &[SymbolId]) -> Vec<SymbolId> {
+        symbols.to_vec()
     }
 }
\ No newline at end of file
@@ -0,0 +1,196 @@
+mod common;
+
+use askl::analysis::{cycle_report, cycles, dead_code, dead_symbols, EntryPoints, UNREACHABLE};
+use askl::cfg::{ControlFlowGraph, EdgeKinds};
+use askl::symbols::SymbolId;
+use askl::{Askl, QueryResult};
+use common::{cfg, symbol_ids, symbols, tmp_path};
+
+const INDEX: &str = r#"{
+  "map": {
+    "main": {"name": "main", "ranges": [], "definition": {"begin": {"file": "a.c", "line": 1, "col": 1}, "end": {"file": "a.c", "line": 6, "col": 1}},
+             "children": {"run": [{"kind": "Call", "range": null}],
+                          "on_event@a.c": [{"kind": "AddressTaken", "range": null}]}},
+    "run": {"name": "run", "ranges": [], "definition": {"begin": {"file": "a.c", "line": 11, "col": 1}, "end": {"file": "a.c", "line": 16, "col": 1}},
+            "children": {"printf": [{"kind": "Call", "range": null}]}},
+    "on_event@a.c": {"name": "on_event", "linkage": "Internal", "ranges": [], "definition": {"begin": {"file": "a.c", "line": 21, "col": 1}, "end": {"file": "a.c", "line": 26, "col": 1}},
+                     "children": {}},
+    "legacy": {"name": "legacy", "ranges": [], "definition": {"begin": {"file": "a.c", "line": 31, "col": 1}, "end": {"file": "a.c", "line": 36, "col": 1}},
+               "children": {"old_helper@a.c": [{"kind": "Call", "range": null}],
+                            "on_timer@a.c": [{"kind": "CallbackArgument", "range": null}]}},
+    "old_helper@a.c": {"name": "old_helper", "linkage": "Internal", "ranges": [], "definition": {"begin": {"file": "a.c", "line": 41, "col": 1}, "end": {"file": "a.c", "line": 46, "col": 1}},
+                       "children": {"old_helper@a.c": [{"kind": "Call", "range": null}]}},
+    "on_timer@a.c": {"name": "on_timer", "linkage": "Internal", "ranges": [], "definition": {"begin": {"file": "a.c", "line": 51, "col": 1}, "end": {"file": "a.c", "line": 56, "col": 1}},
+                     "children": {}},
+    "my_open@b.c": {"name": "my_open", "linkage": "Internal", "ranges": [], "definition": {"begin": {"file": "b.c", "line": 61, "col": 1}, "end": {"file": "b.c", "line": 66, "col": 1}},
+                    "children": {}},
+    "log_error": {"name": "log_error", "ranges": [{"begin": {"file": "log.h", "line": 1, "col": 1}, "end": {"file": "log.h", "line": 1, "col": 20}}], "children": {}},
+    "__bswap_32@/usr/include/bits/byteswap.h": {"name": "__bswap_32", "linkage": "Internal", "ranges": [],
+                                                "definition": {"begin": {"file": "/usr/include/bits/byteswap.h", "line": 1, "col": 1}, "end": {"file": "/usr/include/bits/byteswap.h", "line": 5, "col": 1}},
+                                                "children": {}},
+    "test_run": {"name": "run_all", "scope": "test", "ranges": [], "definition": {"begin": {"file": "a.c", "line": 71, "col": 1}, "end": {"file": "a.c", "line": 76, "col": 1}},
+                 "children": {}}
+  },
+  "fields": {"file_ops.open": ["my_open@b.c"]}
+}"#;
+
+fn ids(ids: &[SymbolId]) -> Vec<&str> {
+    ids.iter().map(SymbolId::as_str).collect()
+}
+
+fn result_ids(result: &QueryResult) -> Vec<&str> {
+    result.symbols.iter().map(|symbol| symbol.id.as_str()).collect()
+}
+
+#[test]
+fn dead() {
+    let cfg = cfg(INDEX);
+    let main = EntryPoints {
+        names: vec!["main".into()],
+        ..Default::default()
+    };
+    // Address-taken functions are entry points of their own, even when the
+    // code taking the address is dead. Functions only declared or defined in
+    // system headers are not reported.
+    assert_eq!(ids(&dead_symbols(&cfg, &main)), ["legacy", "old_helper@a.c", "test_run"]);
+    let path = tmp_path("dead.idx");
+    symbols(INDEX).save(&path).unwrap();
+    let from_index = ControlFlowGraph::load(&path).unwrap();
+    assert_eq!(ids(&dead_symbols(&from_index, &main)), ["legacy", "old_helper@a.c", "test_run"]);
+
+    let strict = EntryPoints { strict: true, ..main.clone() };
+    assert_eq!(
+        ids(&dead_symbols(&cfg, &strict)),
+        ["legacy", "my_open@b.c", "old_helper@a.c", "on_timer@a.c", "test_run"]
+    );
+
+    let exported = EntryPoints {
+        exported: true,
+        strict: true,
+        ..Default::default()
+    };
+    assert_eq!(ids(&dead_symbols(&cfg, &exported)), ["my_open@b.c"]);
+
+    let result = dead_code(&cfg, &strict);
+    let edges: Vec<(&str, &str)> = result
+        .edges
+        .iter()
+        .map(|edge| (edge.from.as_str(), edge.to.as_str()))
+        .collect();
+    assert_eq!(
+        edges,
+        [("legacy", "old_helper@a.c"), ("legacy", "on_timer@a.c"), ("old_helper@a.c", "old_helper@a.c")]
+    );
+    // Functions only dead code refers to are told from the ones nothing does
+    let unreachable: Vec<&str> = result
+        .symbols
+        .iter()
+        .filter(|symbol| symbol.group.as_deref() == Some(UNREACHABLE))
+        .map(|symbol| symbol.id.as_str())
+        .collect();
+    assert_eq!(unreachable, ["old_helper@a.c", "on_timer@a.c"]);
+}
+
+#[test]
+fn entry_points() {
+    let cfg = cfg(INDEX);
+    let mut entries: EntryPoints = "# Entry points\nmain\n\n/^test::/\nlegacy\n".parse().unwrap();
+    assert_eq!(entries.names, ["main", "legacy"]);
+    entries.strict = true;
+    assert_eq!(ids(&dead_symbols(&cfg, &entries)), ["my_open@b.c"]);
+    assert!("/(/".parse::<EntryPoints>().is_err());
+}
+
+#[test]
+fn verb() {
+    let cfg = cfg(INDEX);
+    let run = |query: &str| symbol_ids(&cfg, query);
+    assert_eq!(run("@dead"), ["legacy", "old_helper@a.c", "test_run"]);
+    assert_eq!(run(r#"@dead(entry="legacy", regex="^run_", strict="true")"#), ["main", "my_open@b.c", "on_event@a.c", "run"]);
+    assert_eq!(run(r#"@dead(exported="true") "my_open""#), Vec::<String>::new());
+    assert!(Askl::new(r#"@dead(entries="main")"#).is_err());
+    // Configuration files are read by the command line only
+    assert!(Askl::new(r#"@dead(config="entries.txt")"#).is_err());
+
+    // A compiled query finds the dead code of each graph it runs on
+    let other = common::cfg(r#"{"map": {
+        "main": {"name": "main", "ranges": [], "definition": {"begin": {"file": "c.c", "line": 1, "col": 1}, "end": {"file": "c.c", "line": 3, "col": 1}},
+                 "children": {}},
+        "orphan": {"name": "orphan", "ranges": [], "definition": {"begin": {"file": "c.c", "line": 5, "col": 1}, "end": {"file": "c.c", "line": 7, "col": 1}},
+                   "children": {}}
+    }}"#);
+    let dead = Askl::new("@dead").unwrap();
+    assert_eq!(result_ids(&dead.run(&cfg)), ["legacy", "old_helper@a.c", "test_run"]);
+    assert_eq!(result_ids(&dead.run(&other)), ["orphan"]);
+}
+
+const RECURSIVE: &str = r#"{
+  "map": {
+    "main": {"name": "main", "ranges": [], "children": {"expr": [{"kind": "Call", "range": null}], "fact": [{"kind": "Call", "range": null}]}},
+    "expr": {"name": "expr", "ranges": [], "children": {"term": [{"kind": "Call", "range": null}]}},
+    "term": {"name": "term", "ranges": [], "children": {"expr": [{"kind": "Call", "range": null}], "visit": [{"kind": "AddressTaken", "range": null}]}},
+    "visit": {"name": "visit", "ranges": [], "children": {"term": [{"kind": "Call", "range": null}]}},
+    "fact": {"name": "fact", "ranges": [], "children": {"fact": [{"kind": "Call", "range": null}]}}
+  }
+}"#;
+
+#[test]
+fn recursion() {
+    let cfg = cfg(RECURSIVE);
+    let found = cycles(&cfg, EdgeKinds::calls());
+    let members: Vec<Vec<&str>> = found.iter().map(|cycle| ids(&cycle.symbols)).collect();
+    assert_eq!(members, [vec!["expr", "term"], vec!["fact"]]);
+    let edges: Vec<(&str, &str)> = found[0]
+        .edges
+        .iter()
+        .map(|(from, to)| (from.as_str(), to.as_str()))
+        .collect();
+    assert_eq!(edges, [("expr", "term"), ("term", "expr")]);
+
+    // Taking an address closes a cycle only when asked for
+    let found = cycles(&cfg, "call,address".parse().unwrap());
+    assert_eq!(ids(&found[0].symbols), ["expr", "term", "visit"]);
+
+    let report = cycle_report(&cfg, EdgeKinds::calls());
+    let groups: Vec<(&str, Option<&str>)> = report
+        .symbols
+        .iter()
+        .map(|symbol| (symbol.id.as_str(), symbol.group.as_deref()))
+        .collect();
+    assert_eq!(
+        groups,
+        [("expr", Some("cycle 1")), ("fact", Some("cycle 2")), ("term", Some("cycle 1"))]
+    );
+    assert_eq!(report.edges.len(), 3);
+
+    let run = |query: &str| symbol_ids(&cfg, query);
+    assert_eq!(run("@recursive"), ["expr", "fact", "term"]);
+    assert_eq!(run(r#"@scc(kinds="call,address") "visit""#), ["visit"]);
+    // The callees of each function are filtered by the same cycles
+    assert_eq!(run(r#""main" {@recursive "expr" {}}"#), ["expr", "main", "term"]);
+
+    // A compiled query finds the cycles of each graph it runs on
+    let recursive = Askl::new("@recursive").unwrap();
+    assert_eq!(result_ids(&recursive.run(&cfg)), ["expr", "fact", "term"]);
+    assert_eq!(result_ids(&recursive.run(&common::cfg(INDEX))), ["old_helper@a.c"]);
+    assert!(Askl::new(r#"@scc(kinds="calls")"#).is_err());
+}
+
+#[test]
+fn structure() {
+    let cfg = cfg(RECURSIVE);
+    let run = |query: &str| symbol_ids(&cfg, query);
+    // Calls of a symbol to itself do not count
+    assert_eq!(run("@leaf"), ["fact"]);
+    assert_eq!(run("@root"), ["main"]);
+    assert_eq!(run(r#"@fanin(min="2")"#), ["expr", "term"]);
+    assert_eq!(run(r#"@fanout(min="2")"#), ["main", "term"]);
+    assert_eq!(run(r#"@fanout(min="1", max="1")"#), ["expr", "visit"]);
+    assert_eq!(run(r#"@leaf(kinds="call")"#), ["fact"]);
+    assert_eq!(run(r#"@fanout(max="1", kinds="call") @fanin(min="1")"#), ["expr", "fact", "term", "visit"]);
+
+    assert!(Askl::new("@fanin").is_err());
+    assert!(Askl::new(r#"@fanout(min="3", max="2")"#).is_err());
+    assert!(Askl::new(r#"@fanout(min="-1")"#).is_err());
+    assert!(Askl::new(r#"@root(max="1")"#).is_err());
+}
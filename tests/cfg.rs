@@ -0,0 +1,50 @@
+mod common;
+
+use askl::symbols::SymbolId;
+use common::cfg;
+
+const INDEX: &str = r#"{"map": {
+    "main": {"name": "main", "ranges": [], "children": {"foo": [{"kind": "Call", "range": null}], "bar": [{"kind": "Call", "range": null}]}},
+    "foo": {"name": "foo", "ranges": [], "children": {"free": [{"kind": "Call", "range": null}], "baz": [{"kind": "Call", "range": null}]}},
+    "bar": {"name": "bar", "ranges": [], "children": {"foo": [{"kind": "AddressTaken", "range": null}, {"kind": "Call", "range": null}]}},
+    "baz": {"name": "baz", "ranges": [], "children": {}}
+}}"#;
+
+fn id(name: &str) -> SymbolId {
+    SymbolId::new(name.into())
+}
+
+fn sorted(mut ids: Vec<SymbolId>) -> Vec<SymbolId> {
+    ids.sort();
+    ids
+}
+
+#[test]
+fn graph() {
+    let cfg = cfg(INDEX);
+    // Symbols only known as callees get a node too, and references of
+    // several kinds between two symbols share an edge
+    assert_eq!(cfg.graph().node_count(), 5);
+    assert_eq!(cfg.graph().edge_count(), 5);
+    let free = cfg.node_id(&id("free")).unwrap();
+    assert_eq!(cfg.symbol_id(free), &id("free"));
+    assert_eq!(cfg.node_id(&id("missing")), None);
+
+    assert_eq!(sorted(cfg.neighbors(&id("foo"))), [id("baz"), id("free")]);
+    assert_eq!(sorted(cfg.predecessors(&id("foo"))), [id("bar"), id("main")]);
+    assert!(cfg.neighbors(&id("missing")).is_empty());
+    assert_eq!((cfg.in_degree(&id("foo")), cfg.out_degree(&id("foo"))), (2, 2));
+    assert_eq!((cfg.in_degree(&id("main")), cfg.out_degree(&id("free"))), (0, 0));
+}
+
+#[test]
+fn subgraph() {
+    let cfg = cfg(INDEX);
+    let sub = cfg.subgraph(&[id("main"), id("foo"), id("free")]);
+    assert_eq!(sub.graph().node_count(), 3);
+    assert_eq!(sub.neighbors(&id("main")), [id("foo")]);
+    assert_eq!(sub.neighbors(&id("foo")), [id("free")]);
+    assert_eq!(sub.predecessors(&id("foo")), [id("main")]);
+    assert_eq!(sub.node_id(&id("bar")), None);
+    assert_eq!(sub.get_symbol(&id("main")).unwrap().name, "main");
+}
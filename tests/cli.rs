@@ -0,0 +1,86 @@
+mod common;
+
+use common::tmp_path;
+use serde_json::Value;
+use std::process::Command;
+
+const INDEX: &str = r#"{"map": {
+    "main": {"name": "main", "ranges": [], "children": {"foo": [{"kind": "Call", "range": null}]}},
+    "foo": {"name": "foo", "ranges": [], "children": {"free": [{"kind": "Call", "range": null}]}}
+}}"#;
+
+/// Run askl on `INDEX`, returning the JSON it prints
+fn askl(name: &str, args: &[&str]) -> Value {
+    askl_on(name, INDEX, args).0
+}
+
+/// Run askl on the given index, returning the JSON it prints and what it
+/// reports on the standard error
+fn askl_on(name: &str, json: &str, args: &[&str]) -> (Value, String) {
+    let index = tmp_path(name);
+    std::fs::write(&index, json).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_askl"))
+        .args(["-i", index.to_str().unwrap(), "-f", "json"])
+        .args(args)
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr).into_owned();
+    assert!(output.status.success(), "{}", stderr);
+    (serde_json::from_slice(&output.stdout).unwrap(), stderr)
+}
+
+fn node_ids(graph: &Value) -> Vec<&str> {
+    graph["nodes"].as_array().unwrap().iter().map(|node| node["id"].as_str().unwrap()).collect()
+}
+
+#[test]
+fn path() {
+    let graph = askl("cli-path.json", &["path", "--from", "main", "--to", "free"]);
+    assert_eq!(node_ids(&graph), ["foo", "free", "main"]);
+
+    let graph = askl("cli-path-self.json", &["path", "--from", "main", "--to", "main"]);
+    assert_eq!(node_ids(&graph), ["main"]);
+    assert!(graph["edges"].as_array().unwrap().is_empty());
+
+    let graph = askl("cli-path-empty.json", &["path", "--from", "main", "--to", "free", "--all", "--max-length", "0"]);
+    assert!(node_ids(&graph).is_empty());
+
+    let graph = askl("cli-path-kinds.json", &["path", "--from", "main", "--to", "free", "--kinds", "address"]);
+    assert!(node_ids(&graph).is_empty());
+}
+
+/// `unused` calls `helper`, which nothing else calls
+const DEAD: &str = r#"{"map": {
+    "main": {"name": "main", "ranges": [], "definition": {"begin": {"file": "a.c", "line": 1, "col": 1}, "end": {"file": "a.c", "line": 3, "col": 1}},
+             "children": {}},
+    "unused": {"name": "unused", "ranges": [], "definition": {"begin": {"file": "a.c", "line": 5, "col": 1}, "end": {"file": "a.c", "line": 7, "col": 1}},
+               "children": {"helper": [{"kind": "Call", "range": null}]}},
+    "helper": {"name": "helper", "ranges": [], "definition": {"begin": {"file": "a.c", "line": 9, "col": 1}, "end": {"file": "a.c", "line": 11, "col": 1}},
+               "children": {}}
+}}"#;
+
+#[test]
+fn dead() {
+    let (graph, stderr) = askl_on("cli-dead.json", DEAD, &["dead"]);
+    let groups: Vec<(&str, &str)> = graph["nodes"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|node| (node["id"].as_str().unwrap(), node["group"].as_str().unwrap()))
+        .collect();
+    assert_eq!(groups, [("helper", "unreachable"), ("unused", "never referenced")]);
+    assert!(stderr.contains("Never referenced: unused\n"), "{}", stderr);
+    assert!(stderr.contains("Only referenced by dead code: 1\n"), "{}", stderr);
+}
+
+#[test]
+fn shell_options() {
+    let index = tmp_path("cli-shell.json");
+    std::fs::write(&index, INDEX).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_askl"))
+        .args(["-i", index.to_str().unwrap(), "-o", tmp_path("cli-shell.dot").to_str().unwrap()])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains(":save"));
+}
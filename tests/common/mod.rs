@@ -0,0 +1,41 @@
+// Every test crate uses only some of the helpers
+#![allow(dead_code)]
+
+use askl::cfg::ControlFlowGraph;
+use askl::symbols::SymbolMap;
+use askl::{Askl, QueryResult};
+use std::path::PathBuf;
+
+/// Symbol map given as the JSON `gen-ast` writes
+pub fn symbols(json: &str) -> SymbolMap {
+    serde_json::from_str(json).unwrap()
+}
+
+pub fn cfg(json: &str) -> ControlFlowGraph {
+    ControlFlowGraph::from_symbols(symbols(json))
+}
+
+/// Path for a file written by a test
+pub fn tmp_path(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join(name)
+}
+
+pub fn query(cfg: &ControlFlowGraph, query: &str) -> QueryResult {
+    Askl::new(query).unwrap().run(cfg)
+}
+
+/// Ids of the symbols a query results in
+pub fn symbol_ids(cfg: &ControlFlowGraph, text: &str) -> Vec<String> {
+    query(cfg, text).symbols.iter().map(|symbol| symbol.id.to_string()).collect()
+}
+
+/// Edges a query results in, sorted
+pub fn edges(cfg: &ControlFlowGraph, text: &str) -> Vec<(String, String)> {
+    let mut edges: Vec<(String, String)> = query(cfg, text)
+        .edges
+        .iter()
+        .map(|edge| (edge.from.to_string(), edge.to.to_string()))
+        .collect();
+    edges.sort();
+    edges
+}
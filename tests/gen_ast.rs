@@ -0,0 +1,576 @@
+// The stand-in for clang is a shell script
+#![cfg(unix)]
+
+mod common;
+
+use askl::symbols::{EdgeKind, Linkage, Symbol, SymbolId, SymbolMap};
+use common::tmp_path;
+use serde_json::{json, Value};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Prints the AST stored next to the compiled source, as clang would with
+/// `-Xclang -ast-dump=json`, and lists the headers named next to it as its
+/// dependencies for `-MF`
+const CLANG: &str = r#"#!/bin/sh
+while [ $# -gt 0 ]; do
+    case "$1" in
+        --version) echo "clang version 0.0"; exit ;;
+        -MF) depfile="$2"; shift ;;
+        *.c|*.cpp) source="$1" ;;
+    esac
+    shift
+done
+if [ -n "$depfile" ]; then
+    printf '%s: %s \\\n  %s\n' "$source.o" "$source" "$(cat "$source.deps" 2>/dev/null)" > "$depfile"
+fi
+cat "$source.json"
+"#;
+
+/// Run gen-ast over translation units given as the ASTs clang dumps for them
+fn gen_ast(name: &str, units: &[(&str, Value)]) -> SymbolMap {
+    let dir = project(name, units);
+    run(&dir, &[]);
+    SymbolMap::load(dir.join("symbol_map.json")).unwrap()
+}
+
+/// Directory with the translation units, their compile commands and clang
+fn project(name: &str, units: &[(&str, Value)]) -> PathBuf {
+    let dir = tmp_path(name);
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let clang = dir.join("clang");
+    std::fs::write(&clang, CLANG).unwrap();
+    std::fs::set_permissions(&clang, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+    let mut commands = vec![];
+    for (file, ast) in units.iter() {
+        std::fs::write(dir.join(file), "").unwrap();
+        std::fs::write(dir.join(format!("{}.json", file)), clang_json(ast)).unwrap();
+        commands.push(json!({
+            "directory": dir,
+            "arguments": ["cc", "-c", file, "-o", format!("{}.o", file)],
+            "file": file,
+        }));
+    }
+    std::fs::write(dir.join("compile_commands.json"), Value::from(commands).to_string()).unwrap();
+    dir
+}
+
+fn run(dir: &Path, args: &[&str]) {
+    let output = Command::new(env!("CARGO_BIN_EXE_gen-ast"))
+        .arg(dir.join("compile_commands.json"))
+        .arg("--clang")
+        .arg(dir.join("clang"))
+        .arg("--output")
+        .arg(dir.join("symbol_map.json"))
+        .args(args)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    // Translation units failing to parse are only reported
+    assert!(output.stdout.is_empty(), "{}", String::from_utf8_lossy(&output.stdout));
+}
+
+/// Keys in the order clang writes them, the parser relies on the kind
+/// preceding the rest of a node and on the order of the parts of a location
+const CLANG_KEYS: [&str; 7] = ["id", "kind", "offset", "file", "line", "col", "tokLen"];
+
+/// JSON text of an AST as clang writes it
+fn clang_json(value: &Value) -> String {
+    match value {
+        Value::Object(object) => {
+            let mut keys: Vec<&String> = object.keys().collect();
+            keys.sort_by_key(|key| CLANG_KEYS.iter().position(|first| first == key).unwrap_or(CLANG_KEYS.len()));
+            let fields: Vec<String> = keys
+                .into_iter()
+                .map(|key| format!("{}:{}", Value::from(key.as_str()), clang_json(&object[key])))
+                .collect();
+            format!("{{{}}}", fields.join(","))
+        }
+        Value::Array(array) => format!("[{}]", array.iter().map(clang_json).collect::<Vec<_>>().join(",")),
+        value => value.to_string(),
+    }
+}
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(0x1000);
+
+/// Id of a node no other node refers to
+fn id() -> String {
+    format!("{:#x}", NEXT_ID.fetch_add(1, Ordering::Relaxed))
+}
+
+fn loc(file: &str, line: u32) -> Value {
+    json!({"offset": 0, "file": file, "line": line, "col": 1, "tokLen": 1})
+}
+
+fn range(file: &str, line: u32) -> Value {
+    json!({"begin": loc(file, line), "end": loc(file, line)})
+}
+
+/// Add the fields of `fields` to the object `value`
+fn with(mut value: Value, fields: Value) -> Value {
+    for (key, field) in fields.as_object().unwrap() {
+        value[key] = field.clone();
+    }
+    value
+}
+
+fn tu(decls: Vec<Value>) -> Value {
+    json!({"id": "0x1", "kind": "TranslationUnitDecl", "loc": {}, "range": {"begin": {}, "end": {}}, "inner": decls})
+}
+
+/// Declaration of `void name(void)`, a prototype unless given a body
+fn function(id: &str, name: &str, file: &str, line: u32, body: Option<Vec<Value>>) -> Value {
+    let decl = json!({
+        "id": id, "kind": "FunctionDecl", "name": name, "loc": loc(file, line), "range": range(file, line),
+        "type": {"qualType": "void (void)"},
+    });
+    match body {
+        Some(body) => with(decl, json!({"inner": [{"id": self::id(), "kind": "CompoundStmt", "inner": body}]})),
+        None => decl,
+    }
+}
+
+fn decl_ref(target: &str, name: &str, file: &str, line: u32) -> Value {
+    json!({
+        "id": id(), "kind": "DeclRefExpr", "range": range(file, line),
+        "referencedDecl": {"id": target, "kind": "FunctionDecl", "name": name, "type": {"qualType": "void (void)"}},
+    })
+}
+
+fn call(target: &str, name: &str, file: &str, line: u32, args: Vec<Value>) -> Value {
+    let callee = json!({"id": id(), "kind": "ImplicitCastExpr", "castKind": "FunctionToPointerDecay", "inner": [decl_ref(target, name, file, line)]});
+    let inner: Vec<Value> = [callee].into_iter().chain(args).collect();
+    json!({"id": id(), "kind": "CallExpr", "range": range(file, line), "inner": inner})
+}
+
+fn symbol<'a>(symbols: &'a SymbolMap, id: &str) -> &'a Symbol {
+    symbols.map.get(&SymbolId::new(id.into())).unwrap_or_else(|| panic!("no symbol {}", id))
+}
+
+/// Callees of a symbol with the kinds of references to each, sorted
+fn children(symbols: &SymbolMap, id: &str) -> Vec<(String, Vec<EdgeKind>)> {
+    let mut children: Vec<(String, Vec<EdgeKind>)> = symbol(symbols, id)
+        .children
+        .iter()
+        .map(|(child, references)| (child.to_string(), references.iter().map(|reference| reference.kind).collect()))
+        .collect();
+    children.sort();
+    children
+}
+
+fn ids(symbols: &SymbolMap) -> Vec<&str> {
+    let mut ids: Vec<&str> = symbols.map.keys().map(|id| id.as_str()).collect();
+    ids.sort();
+    ids
+}
+
+#[test]
+fn linkage() {
+    // a.c: static void init(void); static void init(void) { helper(); }
+    //      void a_main(void) { init(); }
+    // b.c: static void init(void) {} void helper(void) {}
+    //      void main(void) { init(); helper(); }
+    let a = tu(vec![
+        function("0x20", "helper", "api.h", 1, None),
+        with(function("0x21", "init", "a.c", 3, None), json!({"storageClass": "static"})),
+        with(
+            function("0x22", "init", "a.c", 10, Some(vec![call("0x20", "helper", "a.c", 11, vec![])])),
+            json!({"storageClass": "static", "previousDecl": "0x21"}),
+        ),
+        function("0x23", "a_main", "a.c", 20, Some(vec![call("0x22", "init", "a.c", 21, vec![])])),
+    ]);
+    let b = tu(vec![
+        function("0x20", "helper", "api.h", 1, None),
+        with(function("0x30", "init", "b.c", 5, Some(vec![])), json!({"storageClass": "static"})),
+        with(function("0x31", "helper", "b.c", 9, Some(vec![])), json!({"previousDecl": "0x20"})),
+        function("0x32", "main", "b.c", 20, Some(vec![call("0x30", "init", "b.c", 21, vec![]), call("0x31", "helper", "b.c", 22, vec![])])),
+    ]);
+    let symbols = gen_ast("gen-ast-linkage", &[("a.c", a), ("b.c", b)]);
+
+    // Static functions of different files are told apart, declarations of
+    // the same function are merged
+    assert_eq!(ids(&symbols), ["a_main", "helper", "init@a.c", "init@b.c", "main"]);
+    assert_eq!(symbol(&symbols, "init@a.c").linkage, Linkage::Internal);
+    assert_eq!(symbol(&symbols, "init@a.c").name, "init");
+    assert_eq!(symbol(&symbols, "helper").linkage, Linkage::External);
+    assert_eq!(children(&symbols, "a_main"), [("init@a.c".into(), vec![EdgeKind::Call])]);
+    assert_eq!(children(&symbols, "init@a.c"), [("helper".into(), vec![EdgeKind::Call])]);
+    assert_eq!(
+        children(&symbols, "main"),
+        [("helper".into(), vec![EdgeKind::Call]), ("init@b.c".into(), vec![EdgeKind::Call])]
+    );
+}
+
+#[test]
+fn locations() {
+    // a.c: static void init(void); void helper(void);
+    //      static void init(void) { helper(); init(); }
+    let a = tu(vec![
+        with(function("0x21", "init", "a.c", 3, None), json!({"storageClass": "static"})),
+        function("0x22", "helper", "a.c", 4, None),
+        with(
+            function("0x23", "init", "a.c", 10, Some(vec![call("0x22", "helper", "a.c", 11, vec![]), call("0x21", "init", "a.c", 12, vec![])])),
+            json!({"storageClass": "static", "previousDecl": "0x21"}),
+        ),
+    ]);
+    // b.c: void helper(void) {}
+    let b = tu(vec![function("0x30", "helper", "b.c", 1, Some(vec![]))]);
+    let symbols = gen_ast("gen-ast-locations", &[("a.c", a), ("b.c", b)]);
+
+    // Symbols are located at their definition, not at their first
+    // declaration
+    let init = symbol(&symbols, "init@a.c");
+    assert_eq!(init.ranges.len(), 2);
+    assert_eq!(init.location().unwrap().line, 10);
+    let helper = symbol(&symbols, "helper");
+    assert_eq!(helper.ranges.len(), 2);
+    assert_eq!(helper.definition.as_ref().unwrap().begin.file, "b.c");
+    assert_eq!(helper.location().unwrap().file, "b.c");
+
+    // Every reference is recorded with where it is made
+    let mut sites: Vec<usize> = init
+        .children
+        .values()
+        .flatten()
+        .map(|reference| reference.range.as_ref().unwrap().begin.line)
+        .collect();
+    sites.sort();
+    assert_eq!(sites, [11, 12]);
+}
+
+#[test]
+fn merged_units() {
+    // trace.h: inline void trace(void) { #ifdef DEBUG dump(); #else emit(); #endif }
+    // a.c, built with -DDEBUG: void a(void) { trace(); }
+    // b.c: void b(void) { trace(); }
+    let a = tu(vec![
+        function("0x20", "trace", "trace.h", 1, Some(vec![call("0x21", "dump", "trace.h", 3, vec![])])),
+        function("0x22", "a", "a.c", 1, Some(vec![call("0x20", "trace", "a.c", 2, vec![])])),
+    ]);
+    let b = tu(vec![
+        function("0x30", "trace", "trace.h", 1, Some(vec![call("0x31", "emit", "trace.h", 5, vec![])])),
+        function("0x32", "b", "b.c", 1, Some(vec![call("0x30", "trace", "b.c", 2, vec![])])),
+    ]);
+    let symbols = gen_ast("gen-ast-merged-units", &[("a.c", a), ("b.c", b)]);
+
+    // A function compiled in several units keeps the references of all
+    assert_eq!(
+        children(&symbols, "trace"),
+        [("dump".into(), vec![EdgeKind::Call]), ("emit".into(), vec![EdgeKind::Call])]
+    );
+    assert_eq!(symbol(&symbols, "trace").ranges.len(), 1);
+}
+
+#[test]
+fn reference_kinds() {
+    // c.c: void handler(void) {} void reg(void (*)(void));
+    //      void use(void) { reg(handler); p = &handler; handler(); }
+    let handler = |line| decl_ref("0x40", "handler", "c.c", line);
+    let variable = json!({"id": id(), "kind": "DeclRefExpr", "referencedDecl": {"id": "0x99", "kind": "VarDecl", "name": "p"}});
+    let c = tu(vec![
+        function("0x40", "handler", "c.c", 1, Some(vec![])),
+        function("0x41", "reg", "c.c", 2, None),
+        function("0x42", "use", "c.c", 10, Some(vec![
+            call("0x41", "reg", "c.c", 11, vec![json!({"id": id(), "kind": "ImplicitCastExpr", "inner": [handler(11)]})]),
+            json!({"id": id(), "kind": "BinaryOperator", "opcode": "=", "inner": [
+                variable,
+                {"id": id(), "kind": "UnaryOperator", "opcode": "&", "inner": [handler(12)]},
+            ]}),
+            call("0x40", "handler", "c.c", 13, vec![]),
+        ])),
+    ]);
+    let symbols = gen_ast("gen-ast-reference-kinds", &[("c.c", c)]);
+
+    // References are listed in the order they are made
+    assert_eq!(
+        children(&symbols, "use"),
+        [
+            ("handler".into(), vec![EdgeKind::CallbackArgument, EdgeKind::AddressTaken, EdgeKind::Call]),
+            ("reg".into(), vec![EdgeKind::Call]),
+        ]
+    );
+}
+
+#[test]
+fn cache() {
+    // a.c includes api.h
+    let dir = project("gen-ast-cache", &[("a.c", tu(vec![function("0x20", "old", "a.c", 1, Some(vec![]))]))]);
+    std::fs::write(dir.join("api.h"), "void old(void);").unwrap();
+    std::fs::write(dir.join("a.c.deps"), "api.h").unwrap();
+    let dump = |name: &str| {
+        let ast = tu(vec![function("0x20", name, "a.c", 1, Some(vec![]))]);
+        std::fs::write(dir.join("a.c.json"), clang_json(&ast)).unwrap();
+    };
+    let index = || {
+        run(&dir, &[]);
+        let symbols = SymbolMap::load(dir.join("symbol_map.json")).unwrap();
+        ids(&symbols).into_iter().map(String::from).collect::<Vec<_>>()
+    };
+    assert_eq!(index(), ["old"]);
+
+    // Symbols are reused as long as none of the inputs of clang change
+    dump("new");
+    assert_eq!(index(), ["old"]);
+    assert!(!dir.join("a.c.o.ast.d").exists());
+    std::fs::write(dir.join("api.h"), "void new(void);").unwrap();
+    assert_eq!(index(), ["new"]);
+
+    // Entries written by older versions are not trusted
+    dump("newer");
+    std::fs::write(dir.join("a.c.o.ast"), json!({"hash": 42, "symbols": {"map": {}}}).to_string()).unwrap();
+    assert_eq!(index(), ["newer"]);
+
+    // Nor are entries whose dependencies are gone
+    dump("newest");
+    std::fs::remove_file(dir.join("api.h")).unwrap();
+    std::fs::write(dir.join("a.c.deps"), "").unwrap();
+    assert_eq!(index(), ["newest"]);
+}
+
+/// Declaration of a C++ function of the given kind, such as `CXXMethodDecl`
+fn method(kind: &str, id: &str, name: &str, line: u32, mangled: &str, body: Option<Vec<Value>>) -> Value {
+    with(function(id, name, "d.cpp", line, body), json!({"kind": kind, "mangledName": mangled}))
+}
+
+/// Variable of type `ty` used as an expression
+fn variable(id: &str, name: &str, ty: &str) -> Value {
+    json!({"id": self::id(), "kind": "DeclRefExpr", "type": {"qualType": ty}, "referencedDecl": {"id": id, "kind": "VarDecl", "name": name}})
+}
+
+/// Call of a method on `object`, where `range` ends at the name of the method
+fn member_call(target: &str, name: &str, object: Value, range: Value) -> Value {
+    json!({"id": id(), "kind": "CXXMemberCallExpr", "range": range, "inner": [
+        {"id": id(), "kind": "MemberExpr", "name": name, "isArrow": false, "referencedMemberDecl": target, "range": range, "inner": [object]},
+    ]})
+}
+
+/// Construction of an object of the class `ty` by the constructor of type
+/// `constructor`
+fn construct(ty: Value, constructor: &str, line: u32) -> Value {
+    json!({"id": id(), "kind": "CXXConstructExpr", "range": range("d.cpp", line), "type": ty, "ctorType": {"qualType": constructor},
+           "inner": [{"id": id(), "kind": "IntegerLiteral"}]})
+}
+
+#[test]
+fn cpp_scopes() {
+    // namespace ns { struct Foo { Foo(int); void bar(); static void sbar(); }; }
+    // ns::Foo::Foo(int) {}  void ns::Foo::bar() { sbar(); }
+    // template <typename T> void tmpl(T) { ns::Foo::sbar(); }
+    // namespace { void hidden() {} }
+    // int main() { ns::Foo f(1); f.bar(); tmpl(1); hidden(); const ns::Foo g = f; }
+    let int = |declaration: Value| with(declaration, json!({"type": {"qualType": "void (int)"}}));
+    let d = tu(vec![
+        json!({"id": "0x10", "kind": "NamespaceDecl", "name": "ns", "inner": [
+            {"id": "0x11", "kind": "CXXRecordDecl", "name": "Foo", "tagUsed": "struct", "inner": [
+                {"id": "0x12", "kind": "CXXRecordDecl", "name": "Foo", "isImplicit": true},
+                int(method("CXXConstructorDecl", "0x13", "Foo", 3, "_ZN2ns3FooC1Ei", None)),
+                with(
+                    method("CXXConstructorDecl", "0x19", "Foo", 3, "_ZN2ns3FooC1ERKS0_", None),
+                    json!({"isImplicit": true, "type": {"qualType": "void (const Foo &)"}}),
+                ),
+                method("CXXMethodDecl", "0x14", "bar", 4, "_ZN2ns3Foo3barEv", None),
+                method("CXXMethodDecl", "0x15", "sbar", 5, "_ZN2ns3Foo4sbarEv", None),
+            ]},
+        ]}),
+        with(
+            int(method("CXXConstructorDecl", "0x20", "Foo", 8, "_ZN2ns3FooC1Ei", Some(vec![]))),
+            json!({"parentDeclContextId": "0x11", "previousDecl": "0x13"}),
+        ),
+        with(
+            method("CXXMethodDecl", "0x21", "bar", 9, "_ZN2ns3Foo3barEv", Some(vec![call("0x15", "sbar", "d.cpp", 9, vec![])])),
+            json!({"parentDeclContextId": "0x11", "previousDecl": "0x14"}),
+        ),
+        json!({"id": "0x30", "kind": "FunctionTemplateDecl", "name": "tmpl", "inner": [
+            {"id": "0x31", "kind": "TemplateTypeParmDecl", "name": "T"},
+            with(function("0x32", "tmpl", "d.cpp", 11, Some(vec![])), json!({"mangledName": "_Z4tmplIT_EvS0_"})),
+            with(
+                function("0x33", "tmpl", "d.cpp", 11, Some(vec![call("0x15", "sbar", "d.cpp", 11, vec![])])),
+                json!({"mangledName": "_Z4tmplIiEvT_"}),
+            ),
+        ]}),
+        json!({"id": "0x40", "kind": "NamespaceDecl", "inner": [
+            with(function("0x41", "hidden", "d.cpp", 13, Some(vec![])), json!({"mangledName": "_ZN12_GLOBAL__N_16hiddenEv"})),
+        ]}),
+        function("0x50", "main", "d.cpp", 15, Some(vec![
+            json!({"id": id(), "kind": "DeclStmt", "inner": [
+                {"id": "0x77", "kind": "VarDecl", "name": "f", "inner": [construct(json!({"qualType": "ns::Foo"}), "void (int)", 16)]},
+            ]}),
+            member_call("0x14", "bar", variable("0x77", "f", "ns::Foo"), range("d.cpp", 17)),
+            call("0x33", "tmpl", "d.cpp", 18, vec![]),
+            call("0x41", "hidden", "d.cpp", 19, vec![]),
+            construct(json!({"qualType": "const Foo", "desugaredQualType": "const ns::Foo"}), "void (const Foo &)", 20),
+        ])),
+    ]);
+    let symbols = gen_ast("gen-ast-cpp-scopes", &[("d.cpp", d)]);
+
+    // Functions are identified by their mangled names, implicit ones are
+    // left out and instantiations of templates are merged into the template
+    assert_eq!(
+        ids(&symbols),
+        [
+            "_Z4tmplIT_EvS0_",
+            "_ZN12_GLOBAL__N_16hiddenEv@d.cpp",
+            "_ZN2ns3Foo3barEv",
+            "_ZN2ns3Foo4sbarEv",
+            "_ZN2ns3FooC1Ei",
+            "main",
+        ]
+    );
+    let scope = |id| (symbol(&symbols, id).name.as_str(), symbol(&symbols, id).scope.as_deref());
+    assert_eq!(scope("_ZN2ns3FooC1Ei"), ("Foo", Some("ns::Foo")));
+    // Methods defined out of their class are scoped by the class
+    assert_eq!(scope("_ZN2ns3Foo3barEv"), ("bar", Some("ns::Foo")));
+    assert_eq!(scope("_Z4tmplIT_EvS0_"), ("tmpl", None));
+    assert_eq!(scope("_ZN12_GLOBAL__N_16hiddenEv@d.cpp"), ("hidden", Some("(anonymous namespace)")));
+    assert_eq!(symbol(&symbols, "_ZN12_GLOBAL__N_16hiddenEv@d.cpp").linkage, Linkage::Internal);
+    assert_eq!(symbol(&symbols, "_ZN2ns3FooC1Ei").definition.as_ref().unwrap().begin.line, 8);
+
+    // Calls to constructors are found by the class and the type of the
+    // constructor, the implicit copy constructor is not indexed
+    assert_eq!(
+        children(&symbols, "main"),
+        [
+            ("_Z4tmplIT_EvS0_".into(), vec![EdgeKind::Call]),
+            ("_ZN12_GLOBAL__N_16hiddenEv@d.cpp".into(), vec![EdgeKind::Call]),
+            ("_ZN2ns3Foo3barEv".into(), vec![EdgeKind::Call]),
+            ("_ZN2ns3FooC1Ei".into(), vec![EdgeKind::Call]),
+        ]
+    );
+    assert_eq!(children(&symbols, "_Z4tmplIT_EvS0_"), [("_ZN2ns3Foo4sbarEv".into(), vec![EdgeKind::Call])]);
+    assert_eq!(children(&symbols, "_ZN2ns3Foo3barEv"), [("_ZN2ns3Foo4sbarEv".into(), vec![EdgeKind::Call])]);
+}
+
+/// Class defined as `struct name : bases { inner }`
+fn class(id: &str, name: &str, bases: &[&str], inner: Vec<Value>) -> Value {
+    let bases: Vec<Value> = bases.iter().map(|base| json!({"access": "public", "type": {"qualType": base}})).collect();
+    json!({"id": id, "kind": "CXXRecordDecl", "name": name, "tagUsed": "struct", "completeDefinition": true, "bases": bases, "inner": inner})
+}
+
+#[test]
+fn virtual_calls() {
+    // struct Base { virtual void run(); };  struct Mid : Base {};
+    // struct Leaf : Mid { void run() override {} };
+    // struct Other : Base { void run() override {} };
+    // void main(Base *b, Mid *m) { b->run(); m->run(); m->Base::run(); }
+    let source = "void main(Base *b, Mid *m) {\n    b->run();\n    m->run();\n    m->Base::run();\n}\n";
+    let overrides = json!([{"id": "0x11", "kind": "CXXMethodDecl", "name": "run", "type": {"qualType": "void (void)"}}]);
+    let pointer = |id: &str, name: &str, ty: &str| {
+        json!({"id": self::id(), "kind": "ImplicitCastExpr", "castKind": "LValueToRValue", "type": {"qualType": ty}, "inner": [variable(id, name, ty)]})
+    };
+    // Only the position of the name of the method matters
+    let at = |line: u32, method: &str| {
+        let offset = source.find(method).unwrap() + method.len() - "run".len();
+        let name = with(loc("d.cpp", line), json!({"offset": offset}));
+        json!({"begin": name, "end": name})
+    };
+    let d = tu(vec![
+        class("0x10", "Base", &[], vec![with(method("CXXMethodDecl", "0x11", "run", 2, "_ZN4Base3runEv", None), json!({"virtual": true}))]),
+        class("0x20", "Mid", &["Base"], vec![]),
+        class("0x30", "Leaf", &["Mid"], vec![with(
+            method("CXXMethodDecl", "0x31", "run", 5, "_ZN4Leaf3runEv", Some(vec![])),
+            json!({"overrides": overrides}),
+        )]),
+        class("0x40", "Other", &["Base"], vec![with(
+            method("CXXMethodDecl", "0x41", "run", 8, "_ZN5Other3runEv", Some(vec![])),
+            json!({"overrides": overrides}),
+        )]),
+        function("0x50", "main", "d.cpp", 1, Some(vec![
+            member_call("0x11", "run", pointer("0x51", "b", "Base *"), at(2, "b->run")),
+            member_call("0x11", "run", pointer("0x52", "m", "Mid *"), at(3, "m->run")),
+            member_call("0x11", "run", pointer("0x52", "m", "Mid *"), at(4, "m->Base::run")),
+        ])),
+    ]);
+    let dir = project("gen-ast-virtual-calls", &[("d.cpp", d)]);
+    std::fs::write(dir.join("d.cpp"), source).unwrap();
+    run(&dir, &[]);
+    let symbols = SymbolMap::load(dir.join("symbol_map.json")).unwrap();
+
+    // Calls reach the overrides in the classes deriving from the class of
+    // their object, and calls naming the class reach only the named method
+    assert_eq!(
+        children(&symbols, "main"),
+        [
+            ("_ZN4Base3runEv".into(), vec![EdgeKind::Call, EdgeKind::Call, EdgeKind::Call]),
+            ("_ZN4Leaf3runEv".into(), vec![EdgeKind::Virtual, EdgeKind::Virtual]),
+            ("_ZN5Other3runEv".into(), vec![EdgeKind::Virtual]),
+        ]
+    );
+    let main = symbol(&symbols, "main");
+    let objects = |callee: &str| -> Vec<(usize, Option<&str>)> {
+        main.children[&SymbolId::new(callee.into())]
+            .iter()
+            .map(|reference| (reference.range.as_ref().unwrap().begin.line, reference.via.as_deref()))
+            .collect()
+    };
+    assert_eq!(objects("_ZN4Base3runEv"), [(2, Some("Base")), (3, Some("Mid")), (4, None)]);
+    assert_eq!(objects("_ZN4Leaf3runEv"), [(2, Some("Base")), (3, Some("Mid"))]);
+    assert_eq!(objects("_ZN5Other3runEv"), [(2, Some("Base"))]);
+}
+
+#[test]
+fn field_stores() {
+    // struct file_ops { int (*open)(void); void (*close)(void); };
+    let record = || {
+        json!({"id": "0x10", "kind": "RecordDecl", "name": "file_ops", "tagUsed": "struct", "completeDefinition": true, "inner": [
+            {"id": "0x11", "kind": "FieldDecl", "name": "open", "type": {"qualType": "int (*)(void)"}},
+            {"id": "0x12", "kind": "FieldDecl", "name": "close", "type": {"qualType": "void (*)(void)"}},
+        ]})
+    };
+    let cast = |inner: Value| json!({"id": id(), "kind": "ImplicitCastExpr", "inner": [inner]});
+    let field = |field: &str, name: &str, line: u32| {
+        json!({"id": id(), "kind": "MemberExpr", "name": name, "isArrow": true, "referencedMemberDecl": field, "range": range("h.c", line),
+               "inner": [cast(variable("0x77", "ops", "struct file_ops *"))]})
+    };
+    // g.c: static int my_open(void) {} static void my_close(void) {}
+    //      static const struct file_ops ops = { my_open, &my_close };
+    let g = tu(vec![
+        record(),
+        with(function("0x20", "my_open", "g.c", 5, Some(vec![])), json!({"storageClass": "static"})),
+        with(function("0x21", "my_close", "g.c", 8, Some(vec![])), json!({"storageClass": "static"})),
+        json!({"id": "0x30", "kind": "VarDecl", "name": "ops", "storageClass": "static", "inner": [
+            {"id": id(), "kind": "InitListExpr", "type": {"qualType": "const struct file_ops"}, "inner": [
+                cast(decl_ref("0x20", "my_open", "g.c", 11)),
+                {"id": id(), "kind": "UnaryOperator", "opcode": "&", "inner": [decl_ref("0x21", "my_close", "g.c", 12)]},
+            ]},
+        ]}),
+    ]);
+    // h.c: void other_close(void) {}
+    //      void setup(struct file_ops *ops) { ops->close = other_close; }
+    //      void do_close(struct file_ops *ops) { ops->close(); ops->open(); }
+    let h = tu(vec![
+        record(),
+        function("0x20", "other_close", "h.c", 3, Some(vec![])),
+        function("0x40", "setup", "h.c", 5, Some(vec![json!({"id": id(), "kind": "BinaryOperator", "opcode": "=", "inner": [
+            field("0x12", "close", 6),
+            cast(decl_ref("0x20", "other_close", "h.c", 6)),
+        ]})])),
+        function("0x50", "do_close", "h.c", 9, Some(vec![
+            json!({"id": id(), "kind": "CallExpr", "range": range("h.c", 10), "inner": [cast(field("0x12", "close", 10))]}),
+            json!({"id": id(), "kind": "CallExpr", "range": range("h.c", 11), "inner": [cast(field("0x11", "open", 11))]}),
+        ])),
+    ]);
+    let symbols = gen_ast("gen-ast-field-stores", &[("g.c", g), ("h.c", h)]);
+
+    // Functions stored into a field by any file are called through it
+    let mut close = symbols.fields["file_ops.close"].clone();
+    close.sort();
+    assert_eq!(close, [SymbolId::new("my_close@g.c".into()), SymbolId::new("other_close".into())]);
+    assert_eq!(symbols.fields["file_ops.open"], [SymbolId::new("my_open@g.c".into())]);
+    assert_eq!(
+        children(&symbols, "do_close"),
+        [
+            ("my_close@g.c".into(), vec![EdgeKind::Indirect]),
+            ("my_open@g.c".into(), vec![EdgeKind::Indirect]),
+            ("other_close".into(), vec![EdgeKind::Indirect]),
+        ]
+    );
+    let call = &symbol(&symbols, "do_close").children[&SymbolId::new("other_close".into())][0];
+    assert_eq!(call.via.as_deref(), Some("file_ops.close"));
+    assert_eq!(call.range.as_ref().unwrap().begin.line, 10);
+    // Storing a function takes its address
+    assert_eq!(children(&symbols, "setup"), [("other_close".into(), vec![EdgeKind::AddressTaken])]);
+}
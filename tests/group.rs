@@ -0,0 +1,88 @@
+mod common;
+
+use askl::group::{Grouping, ModuleMap, EXTERNAL, OTHER};
+use askl::output::Format;
+use askl::symbols::SymbolId;
+use common::{cfg, query};
+use std::path::Path;
+
+const INDEX: &str = r#"{
+  "map": {
+    "main": {"name": "main", "ranges": [{"begin": {"file": "src/main.c", "line": 1, "col": 1}, "end": {"file": "src/main.c", "line": 9, "col": 1}}],
+             "children": {"send": [{"kind": "Call", "range": {"begin": {"file": "src/main.c", "line": 2, "col": 5}, "end": {"file": "src/main.c", "line": 2, "col": 9}}},
+                                   {"kind": "Call", "range": {"begin": {"file": "src/main.c", "line": 3, "col": 5}, "end": {"file": "src/main.c", "line": 3, "col": 9}}}],
+                          "alloc": [{"kind": "Call", "range": {"begin": {"file": "src/main.c", "line": 4, "col": 5}, "end": {"file": "src/main.c", "line": 4, "col": 9}}}]}},
+    "send": {"name": "send", "ranges": [{"begin": {"file": "include/net.h", "line": 3, "col": 1}, "end": {"file": "include/net.h", "line": 3, "col": 20}},
+                                        {"begin": {"file": "src/net/tcp/send.c", "line": 1, "col": 1}, "end": {"file": "src/net/tcp/send.c", "line": 5, "col": 1}}],
+             "definition": {"begin": {"file": "src/net/tcp/send.c", "line": 1, "col": 1}, "end": {"file": "src/net/tcp/send.c", "line": 5, "col": 1}},
+             "children": {"alloc": [{"kind": "Call", "range": {"begin": {"file": "src/net/tcp/send.c", "line": 2, "col": 5}, "end": {"file": "src/net/tcp/send.c", "line": 2, "col": 9}}}],
+                          "printf": [{"kind": "Call", "range": {"begin": {"file": "src/net/tcp/send.c", "line": 3, "col": 5}, "end": {"file": "src/net/tcp/send.c", "line": 3, "col": 9}}}]}},
+    "alloc": {"name": "alloc", "ranges": [{"begin": {"file": "src/mm/alloc.c", "line": 1, "col": 1}, "end": {"file": "src/mm/alloc.c", "line": 5, "col": 1}}],
+              "children": {}}
+  }
+}"#;
+
+fn edges(result: &askl::QueryResult) -> Vec<(&str, &str, usize)> {
+    result
+        .edges
+        .iter()
+        .map(|edge| (edge.from.as_str(), edge.to.as_str(), edge.weight))
+        .collect()
+}
+
+#[test]
+fn collapse() {
+    let cfg = cfg(INDEX);
+    let result = query(&cfg, "@all {}");
+
+    let by_dir = result.collapse(&cfg, &"dir:2".parse().unwrap());
+    assert!(by_dir.collapsed);
+    let names: Vec<&str> = by_dir.symbols.iter().map(|symbol| symbol.name.as_str()).collect();
+    assert_eq!(names, [EXTERNAL, "src", "src/mm", "src/net"]);
+    assert_eq!(
+        edges(&by_dir),
+        [("src", "src/mm", 1), ("src", "src/net", 2), ("src/net", EXTERNAL, 1), ("src/net", "src/mm", 1)]
+    );
+
+    // Calls within a group are dropped
+    let by_top = result.collapse(&cfg, &"dir:1".parse().unwrap());
+    assert_eq!(edges(&by_top), [("src", EXTERNAL, 1)]);
+
+    let mut out = vec![];
+    by_dir.write(Format::Json, &mut out).unwrap();
+    let json: serde_json::Value = serde_json::from_slice(&out).unwrap();
+    assert_eq!(json["edges"][1]["weight"], 2);
+    assert_eq!(json["edges"][1]["sites"].as_array().unwrap().len(), 2);
+}
+
+#[test]
+fn modules() {
+    let modules: ModuleMap = "# Subsystems\nnet: src/net/**\n\nmm: src/mm/*.c src/net/*.c\n".parse().unwrap();
+    assert_eq!(modules.module(Path::new("src/net/tcp/send.c")), Some("net"));
+    assert_eq!(modules.module(Path::new("src/mm/alloc.c")), Some("mm"));
+    // * does not cross directories
+    assert_eq!(modules.module(Path::new("src/mm/slab/slab.c")), None);
+    assert!("net src/net/**".parse::<ModuleMap>().is_err());
+
+    let cfg = cfg(INDEX);
+    let result = query(&cfg, "@all {}");
+    let collapsed = result.collapse(&cfg, &Grouping::Modules(modules));
+    let names: Vec<&str> = collapsed.symbols.iter().map(|symbol| symbol.name.as_str()).collect();
+    assert_eq!(names, [EXTERNAL, OTHER, "mm", "net"]);
+}
+
+#[test]
+fn clusters() {
+    let cfg = cfg(INDEX);
+    let mut result = query(&cfg, "@all {}");
+    result.group(&cfg, &Grouping::File);
+    assert_eq!(result.get_symbol(&SymbolId::new("alloc".into())).unwrap().group.as_deref(), Some("src/mm/alloc.c"));
+    // Symbols are grouped by their definition, not by their prototype
+    assert_eq!(result.get_symbol(&SymbolId::new("send".into())).unwrap().group.as_deref(), Some("src/net/tcp/send.c"));
+
+    let mut out = vec![];
+    result.write(Format::Dot, &mut out).unwrap();
+    let dot = String::from_utf8(out).unwrap();
+    assert_eq!(dot.matches("subgraph cluster_").count(), 4, "{}", dot);
+    assert!(dot.contains(r#"label = "src/net/tcp/send.c""#), "{}", dot);
+}
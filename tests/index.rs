@@ -0,0 +1,162 @@
+mod common;
+
+use askl::cfg::ControlFlowGraph;
+use askl::index::Index;
+use askl::symbols::{IndexHeader, SymbolId, SymbolMap, SCHEMA_VERSION};
+use common::{query, symbols, tmp_path};
+
+const INDEX: &str = r#"{
+  "map": {
+    "main": {"name": "main", "ranges": [{"begin": {"file": "a.c", "line": 1, "col": 1}, "end": {"file": "a.c", "line": 5, "col": 1}}],
+             "definition": {"begin": {"file": "a.c", "line": 1, "col": 1}, "end": {"file": "a.c", "line": 5, "col": 1}},
+             "children": {"foo": [{"kind": "Call", "range": {"begin": {"file": "a.c", "line": 2, "col": 5}, "end": {"file": "a.c", "line": 2, "col": 9}}}],
+                          "helper@a.c": [{"kind": "AddressTaken", "range": null}]}},
+    "helper@a.c": {"name": "helper", "linkage": "Internal", "ranges": [], "children": {}},
+    "_ZN2ns3Foo3runEv": {"name": "run", "scope": "ns::Foo", "ranges": [], "children": {},
+                         "overrides": ["_ZN2ns4Base3runEv"],
+                         "field_calls": [{"kind": "Indirect", "range": null, "via": "ops.open"}]}
+  },
+  "classes": {"ns::Foo": {"bases": ["ns::Base"]}},
+  "fields": {"ops.open": ["helper@a.c"]}
+}"#;
+
+#[test]
+fn round_trip() {
+    let symbols = symbols(INDEX);
+    let path = tmp_path("round_trip.idx");
+    symbols.save(&path).unwrap();
+
+    let index = Index::open(&path).unwrap();
+    // Symbols only known as callees or overridden methods are numbered too
+    assert_eq!(index.len(), 5);
+    let main = index.find("main").unwrap();
+    assert_eq!(index.name(main), Some("main"));
+    let foo = index.find("foo").unwrap();
+    assert!(!index.is_defined(foo));
+    assert_eq!(index.name(foo), None);
+    assert_eq!(index.find("missing"), None);
+
+    let callees: Vec<&str> = index.callees(main).map(|(node, _)| index.id(node)).collect();
+    assert_eq!(callees, ["foo", "helper@a.c"]);
+    let callers: Vec<&str> = index.callers(foo).map(|(node, _)| index.id(node)).collect();
+    assert_eq!(callers, ["main"]);
+
+    let decoded = serde_json::to_value(index.to_symbol_map()).unwrap();
+    assert_eq!(decoded, serde_json::to_value(&symbols).unwrap());
+    assert_eq!(
+        serde_json::to_value(SymbolMap::load(&path).unwrap()).unwrap(),
+        decoded
+    );
+}
+
+#[test]
+fn queries_match_json() {
+    let symbols = symbols(INDEX);
+    let path = tmp_path("queries.idx");
+    symbols.save(&path).unwrap();
+
+    let from_json = ControlFlowGraph::from_symbols(symbols);
+    let from_index = ControlFlowGraph::load(&path).unwrap();
+
+    // Callers are read from the index, and whole-graph searches build the
+    // graph of the index
+    let texts = [
+        r#""main" {}"#,
+        r#""ns::Foo::run""#,
+        "[!address]{}",
+        "@all",
+        r#""foo" ^{}"#,
+        r#""helper" ^[address]{}"#,
+        r#"@fanin(min="1")"#,
+        "@recursive",
+    ];
+    for text in texts {
+        let expected = serde_json::to_value(query(&from_json, text)).unwrap();
+        let actual = serde_json::to_value(query(&from_index, text)).unwrap();
+        assert_eq!(actual, expected, "{}", text);
+    }
+
+    let helper = SymbolId::new("helper@a.c".into());
+    assert_eq!(from_index.get_symbol(&helper).unwrap().name, "helper");
+    assert_eq!(from_index.find_symbols("run"), [SymbolId::new("_ZN2ns3Foo3runEv".into())]);
+    assert_eq!(from_index.iter_symbols().count(), 3);
+    assert_eq!((from_index.node_count(), from_index.edge_count()), (5, 2));
+    assert_eq!(from_index.predecessors(&SymbolId::new("foo".into())), [SymbolId::new("main".into())]);
+}
+
+#[test]
+fn header() {
+    let mut symbols = symbols(INDEX);
+    let header = IndexHeader::new("test", std::path::Path::new(env!("CARGO_MANIFEST_DIR")));
+    assert_eq!(header.schema_version, SCHEMA_VERSION);
+    symbols.header = Some(header.clone());
+
+    let path = tmp_path("header.idx");
+    symbols.save(&path).unwrap();
+    assert_eq!(Index::open(&path).unwrap().header(), Some(&header));
+    assert_eq!(ControlFlowGraph::load(&path).unwrap().header(), Some(&header));
+
+    // Loading fails with a hint instead of whatever serde makes of the rest
+    let mut stale = serde_json::to_value(&symbols).unwrap();
+    stale["header"]["schema_version"] = SCHEMA_VERSION.saturating_sub(1).into();
+    stale["map"] = serde_json::json!([]);
+    let path = tmp_path("stale.json");
+    std::fs::write(&path, stale.to_string()).unwrap();
+    let err = format!("{:#}", SymbolMap::load(&path).unwrap_err());
+    assert!(err.contains("regenerate it with gen-ast"), "{}", err);
+
+    let mut newer = serde_json::to_value(&symbols).unwrap();
+    newer["header"]["schema_version"] = (SCHEMA_VERSION + 1).into();
+    let path = tmp_path("newer.json");
+    std::fs::write(&path, newer.to_string()).unwrap();
+    let err = format!("{:#}", ControlFlowGraph::load(&path).unwrap_err());
+    assert!(err.contains("upgrade askl"), "{}", err);
+}
+
+#[test]
+fn corrupt() {
+    let path = tmp_path("corrupt.idx");
+    symbols(INDEX).save(&path).unwrap();
+    let bytes = std::fs::read(&path).unwrap();
+
+    // Whatever a word is changed to, opening fails or the index reads without
+    // going out of bounds
+    let path = tmp_path("corrupted.idx");
+    for word in (12..bytes.len()).step_by(4) {
+        let value = u32::from_le_bytes(bytes[word..word + 4].try_into().unwrap());
+        for changed in [value.wrapping_add(1), value.wrapping_sub(1), 100, u32::MAX] {
+            let mut corrupted = bytes.clone();
+            corrupted[word..word + 4].copy_from_slice(&changed.to_le_bytes());
+            std::fs::write(&path, &corrupted).unwrap();
+            if let Ok(index) = Index::open(&path) {
+                index.to_symbol_map();
+                for node in 0..index.len() as u32 {
+                    index.callers(node).count();
+                }
+            }
+        }
+    }
+
+    std::fs::write(&path, &bytes[..bytes.len() - 4]).unwrap();
+    let err = format!("{:#}", Index::open(&path).unwrap_err());
+    assert!(err.contains("out of bounds"), "{}", err);
+}
+
+#[test]
+fn save_replaces() {
+    let path = tmp_path("replaced.idx");
+    symbols(INDEX).save(&path).unwrap();
+    let index = Index::open(&path).unwrap();
+
+    // An index that is mapped keeps its contents when it is saved over
+    symbols(r#"{"map": {}}"#).save(&path).unwrap();
+    assert_eq!(index.name(index.find("main").unwrap()), Some("main"));
+    assert!(Index::open(&path).unwrap().is_empty());
+
+    let leftovers: Vec<_> = std::fs::read_dir(path.parent().unwrap())
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .filter(|name| name.starts_with("replaced.idx."))
+        .collect();
+    assert!(leftovers.is_empty(), "{:?}", leftovers);
+}
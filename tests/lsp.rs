@@ -0,0 +1,225 @@
+use askl::lsp::{read_message, write_message, LspClient, LspIndexer};
+use askl::symbols::{Linkage, SymbolId, SymbolMap};
+use serde_json::{json, Value};
+use std::path::Path;
+use tokio::io::{AsyncRead, AsyncWrite, BufReader};
+
+fn item(name: &str, file: &str, line: u32) -> Value {
+    json!({
+        "name": name,
+        "kind": 12,
+        "uri": format!("file:///project/{}", file),
+        "range": {"start": {"line": line, "character": 0}, "end": {"line": line + 3, "character": 1}},
+        "selectionRange": {"start": {"line": line, "character": 4}, "end": {"line": line, "character": 4 + name.len()}},
+    })
+}
+
+fn range(line: u32) -> Value {
+    json!({"start": {"line": line, "character": 4}, "end": {"line": line, "character": 10}})
+}
+
+/// Add the fields of `fields` to the object `value`
+fn with(mut value: Value, fields: Value) -> Value {
+    for (key, field) in fields.as_object().unwrap() {
+        value[key] = field.clone();
+    }
+    value
+}
+
+/// What a language server knows about a project
+struct Project {
+    /// Reported by `workspace/symbol`
+    symbols: Vec<Value>,
+    /// Call hierarchy items, found by the start of their name
+    items: Vec<Value>,
+    /// Caller, callee and line of each call
+    calls: Vec<(Value, Value, u32)>,
+}
+
+/// Workspace symbol locating a function at the name of `item`
+fn symbol(item: &Value) -> Value {
+    json!({
+        "name": item["name"],
+        "kind": 12,
+        "location": {"uri": item["uri"], "range": item["selectionRange"]},
+    })
+}
+
+/// Stands in for clangd
+async fn mock_server<R: AsyncRead + Unpin, W: AsyncWrite + Unpin>(project: Project, reader: R, mut writer: W) {
+    let mut reader = BufReader::new(reader);
+    while let Some(message) = read_message(&mut reader).await.unwrap() {
+        let method = match message["method"].as_str() {
+            Some(method) => method,
+            // Answers to our own requests
+            None => continue,
+        };
+        let params = &message["params"];
+        let result = match method {
+            "initialize" => json!({"capabilities": {"callHierarchyProvider": true, "workspaceSymbolProvider": true}}),
+            "workspace/symbol" => {
+                // Clients have to cope with requests and notifications from
+                // the server at any time
+                write_message(&mut writer, &json!({"jsonrpc": "2.0", "id": 1000, "method": "window/workDoneProgress/create", "params": {"token": 1}})).await.unwrap();
+                write_message(&mut writer, &json!({"jsonrpc": "2.0", "method": "window/logMessage", "params": {"type": 3, "message": "indexing"}})).await.unwrap();
+                json!(project.symbols)
+            }
+            "textDocument/prepareCallHierarchy" => {
+                let found: Vec<&Value> = project
+                    .items
+                    .iter()
+                    .filter(|item| {
+                        item["uri"] == params["textDocument"]["uri"]
+                            && item["selectionRange"]["start"] == params["position"]
+                    })
+                    .collect();
+                json!(found)
+            }
+            "callHierarchy/outgoingCalls" => {
+                let outgoing: Vec<Value> = project
+                    .calls
+                    .iter()
+                    .filter(|(from, _, _)| *from == params["item"])
+                    .map(|(_, to, line)| json!({"to": to, "fromRanges": [range(*line)]}))
+                    .collect();
+                json!(outgoing)
+            }
+            "callHierarchy/incomingCalls" => {
+                let incoming: Vec<Value> = project
+                    .calls
+                    .iter()
+                    .filter(|(_, to, _)| *to == params["item"])
+                    .map(|(from, _, line)| json!({"from": from, "fromRanges": [range(*line)]}))
+                    .collect();
+                json!(incoming)
+            }
+            "shutdown" => Value::Null,
+            "exit" => break,
+            _ => continue,
+        };
+        let response = json!({"jsonrpc": "2.0", "id": message["id"], "result": result});
+        write_message(&mut writer, &response).await.unwrap();
+    }
+}
+
+async fn index(project: Project) -> SymbolMap {
+    let (client_end, server_end) = tokio::io::duplex(1 << 16);
+    let (server_reader, server_writer) = tokio::io::split(server_end);
+    let server = tokio::spawn(mock_server(project, server_reader, server_writer));
+
+    let (client_reader, client_writer) = tokio::io::split(client_end);
+    let mut client = LspClient::new(client_reader, client_writer);
+    let root = Path::new("/project");
+    client.initialize(root).await.unwrap();
+    let symbols = LspIndexer::new(root).index(&mut client).await.unwrap();
+    client.shutdown().await.unwrap();
+    server.await.unwrap();
+    symbols
+}
+
+fn ids(symbols: &SymbolMap) -> Vec<&str> {
+    let mut ids: Vec<&str> = symbols.map.keys().map(|id| id.as_str()).collect();
+    ids.sort();
+    ids
+}
+
+#[tokio::test]
+async fn call_hierarchy() {
+    // Two static `helper` functions, and `hidden`, which `workspace/symbol`
+    // does not report, so it is only found as a callee
+    let main = item("main", "a.c", 0);
+    let helper_a = item("helper", "a.c", 5);
+    let helper_b = item("helper", "b.c", 2);
+    let work = item("work", "b.c", 6);
+    let hidden = item("hidden", "b.c", 10);
+    let printf = with(item("printf", "b.c", 0), json!({"uri": "file:///usr/include/stdio.h"}));
+    let symbols = index(Project {
+        symbols: vec![
+            symbol(&main),
+            symbol(&helper_a),
+            symbol(&helper_b),
+            symbol(&work),
+            json!({"name": "counter", "kind": 13, "location": {"uri": "file:///project/a.c", "range": range(20)}}),
+        ],
+        items: vec![main.clone(), helper_a.clone(), helper_b.clone(), work.clone(), hidden.clone(), printf.clone()],
+        calls: vec![
+            (main.clone(), helper_a.clone(), 1),
+            (main, work.clone(), 2),
+            (work.clone(), helper_b, 7),
+            (work.clone(), hidden, 8),
+            (work, printf, 9),
+        ],
+    })
+    .await;
+
+    assert_eq!(ids(&symbols), ["helper@a.c", "helper@b.c", "hidden", "main", "printf", "work"]);
+
+    let helper = &symbols.map[&SymbolId::new("helper@b.c".into())];
+    assert_eq!(helper.name, "helper");
+    assert_eq!(helper.linkage, Linkage::Internal);
+    assert_eq!(symbols.map[&SymbolId::new("work".into())].linkage, Linkage::External);
+
+    let main = &symbols.map[&SymbolId::new("main".into())];
+    let location = main.location().unwrap();
+    assert_eq!((location.file.as_str(), location.line, location.col), ("a.c", 1, 1));
+
+    let mut callees: Vec<&str> = main.children.keys().map(|id| id.as_str()).collect();
+    callees.sort();
+    assert_eq!(callees, ["helper@a.c", "work"]);
+
+    // The call is reported by both of its ends, but recorded once
+    let sites = &main.children[&SymbolId::new("work".into())];
+    assert_eq!(sites.len(), 1);
+    let site = sites[0].range.as_ref().unwrap();
+    assert_eq!((site.begin.file.as_str(), site.begin.line), ("a.c", 3));
+
+    let work = &symbols.map[&SymbolId::new("work".into())];
+    assert!(work.children.contains_key(&SymbolId::new("hidden".into())));
+
+    // Functions outside the project root are known, but not defined in it
+    assert!(main.definition.is_some());
+    let printf = &symbols.map[&SymbolId::new("printf".into())];
+    assert!(printf.definition.is_none());
+    assert_eq!(printf.location().unwrap().file, "/usr/include/stdio.h");
+}
+
+#[tokio::test]
+async fn overloads() {
+    // math.cpp: int math::add(int, int) {...} double math::add(double, double) {...}
+    // log.h: void log_line();  log.cpp: void log_line() {...}
+    // main.cpp: int main() { math::add(1, 2); math::add(1.0, 2.0); log_line(); }
+    let clangd = |item: Value, id: &str, detail: &str| with(item, json!({"data": id, "detail": detail}));
+    let add_int = clangd(item("add", "math.cpp", 0), "A1", "math::add");
+    let add_double = clangd(item("add", "math.cpp", 5), "A2", "math::add");
+    let log_decl = clangd(
+        with(item("log_line", "log.h", 1), json!({"range": {"start": {"line": 1, "character": 0}, "end": {"line": 1, "character": 15}}})),
+        "L1",
+        "log_line",
+    );
+    let log_def = clangd(item("log_line", "log.cpp", 3), "L1", "log_line");
+    let main = clangd(item("main", "main.cpp", 0), "M1", "main");
+    let symbols = index(Project {
+        // The server reports the function at its declaration
+        symbols: vec![symbol(&main), symbol(&add_int), symbol(&add_double), symbol(&log_decl)],
+        items: vec![main.clone(), add_int.clone(), add_double.clone(), log_decl, log_def.clone()],
+        calls: vec![(main.clone(), add_int, 1), (main.clone(), add_double, 2), (main, log_def, 3)],
+    })
+    .await;
+
+    // Overloads are kept apart, a declaration is joined with its definition
+    assert_eq!(ids(&symbols), ["log_line", "main", "math::add@math.cpp:1", "math::add@math.cpp:6"]);
+    let add = &symbols.map[&SymbolId::new("math::add@math.cpp:6".into())];
+    assert_eq!((add.name.as_str(), add.scope.as_deref()), ("add", Some("math")));
+    assert_eq!(add.linkage, Linkage::External);
+
+    let log_line = &symbols.map[&SymbolId::new("log_line".into())];
+    assert_eq!(log_line.linkage, Linkage::External);
+    assert_eq!(log_line.ranges.len(), 2);
+    let definition = log_line.definition.as_ref().unwrap();
+    assert_eq!((definition.begin.file.as_str(), definition.begin.line), ("log.cpp", 4));
+
+    let main = &symbols.map[&SymbolId::new("main".into())];
+    let mut callees: Vec<&str> = main.children.keys().map(|id| id.as_str()).collect();
+    callees.sort();
+    assert_eq!(callees, ["log_line", "math::add@math.cpp:1", "math::add@math.cpp:6"]);
+}
@@ -0,0 +1,169 @@
+mod common;
+
+use askl::cfg::EdgeKinds;
+use askl::output::Format;
+use askl::query::{ResultEdge, ResultSymbol};
+use askl::symbols::SymbolId;
+use askl::QueryResult;
+use common::{cfg, query};
+
+/// Two static functions named `init`, and a template whose name has to be
+/// escaped in most formats
+const INDEX: &str = r#"{"map": {
+    "main": {"name": "main", "ranges": [{"begin": {"file": "a.c", "line": 1, "col": 1}, "end": {"file": "a.c", "line": 9, "col": 1}}],
+             "children": {"init@a.c": [{"kind": "Call", "range": {"begin": {"file": "a.c", "line": 2, "col": 5}, "end": {"file": "a.c", "line": 2, "col": 9}}}],
+                          "init@b.c": [{"kind": "AddressTaken", "range": {"begin": {"file": "a.c", "line": 3, "col": 5}, "end": {"file": "a.c", "line": 3, "col": 9}}}]}},
+    "init@a.c": {"name": "init", "linkage": "Internal", "ranges": [{"begin": {"file": "a.c", "line": 10, "col": 1}, "end": {"file": "a.c", "line": 12, "col": 1}}],
+                 "children": {"free": [{"kind": "Call", "range": {"begin": {"file": "a.c", "line": 11, "col": 5}, "end": {"file": "a.c", "line": 11, "col": 9}}}]}},
+    "init@b.c": {"name": "init", "linkage": "Internal", "ranges": [{"begin": {"file": "b.c", "line": 1, "col": 1}, "end": {"file": "b.c", "line": 3, "col": 1}}],
+                 "children": {"_Z4lessIiiEbv": [{"kind": "Call", "range": null}]}},
+    "_Z4lessIiiEbv": {"name": "less<int, int>", "ranges": [], "children": {}}
+}}"#;
+
+fn write(result: &QueryResult, format: Format) -> String {
+    let mut out = vec![];
+    result.write(format, &mut out).unwrap();
+    String::from_utf8(out).unwrap()
+}
+
+fn result() -> QueryResult {
+    query(&cfg(INDEX), r#""main" {}*"#)
+}
+
+#[test]
+fn names() {
+    for name in Format::NAMES {
+        let format: Format = name.parse().unwrap();
+        assert_eq!(format.name(), name);
+    }
+    assert!("svg".parse::<Format>().is_err());
+}
+
+#[test]
+fn dot() {
+    let dot = write(&result(), Format::Dot);
+    assert!(dot.starts_with("digraph {\n"), "{}", dot);
+    // Symbols sharing a name are told apart by their location
+    for label in [r#"label = "\"init (a.c:10)\"""#, r#"label = "\"init (b.c:1)\"""#, r#"label = "\"free\"""#] {
+        assert!(dot.contains(label), "{}", dot);
+    }
+    assert_eq!(dot.matches(" -> ").count(), 4, "{}", dot);
+    assert!(dot.contains(r#"label = "\"a.c:3 (address)\"""#), "{}", dot);
+}
+
+#[test]
+fn json() {
+    let json: serde_json::Value = serde_json::from_str(&write(&result(), Format::Json)).unwrap();
+    let nodes: Vec<&str> = json["nodes"].as_array().unwrap().iter().map(|node| node["id"].as_str().unwrap()).collect();
+    assert_eq!(nodes, ["_Z4lessIiiEbv", "free", "init@a.c", "init@b.c", "main"]);
+    assert_eq!(json["nodes"][2]["location"], serde_json::json!({"file": "a.c", "line": 10, "col": 1}));
+    assert_eq!(json["nodes"][1]["location"], serde_json::Value::Null);
+
+    let edge = &json["edges"][3];
+    assert_eq!((edge["from"].as_str(), edge["to"].as_str()), (Some("main"), Some("init@b.c")));
+    assert_eq!(edge["kinds"], serde_json::json!(["address"]));
+    assert_eq!(edge["sites"][0]["range"]["begin"]["line"], 3);
+    assert_eq!(edge["weight"], 1);
+    assert!(json["nodes"][0].get("group").is_none());
+}
+
+#[test]
+fn graphml() {
+    let graphml = write(&result(), Format::GraphMl);
+    assert!(graphml.contains(r#"<data key="name">less&lt;int, int&gt;</data>"#), "{}", graphml);
+    assert!(graphml.contains(r#"<data key="location">a.c:10</data>"#), "{}", graphml);
+    assert!(graphml.contains(
+        "    <edge source=\"main\" target=\"init@b.c\">\n      \
+         <data key=\"kinds\">address</data>\n      \
+         <data key=\"sites\">a.c:3 (address)</data>\n      \
+         <data key=\"weight\">1</data>\n    </edge>\n"
+    ), "{}", graphml);
+    assert_eq!(graphml.matches("<node ").count(), 5);
+    assert!(graphml.ends_with("  </graph>\n</graphml>\n"));
+}
+
+#[test]
+fn mermaid() {
+    assert_eq!(
+        write(&result(), Format::Mermaid),
+        "flowchart LR\n    \
+         n0[\"less<int, int>\"]\n    \
+         n1[\"free\"]\n    \
+         n2[\"init (a.c:10)\"]\n    \
+         n3[\"init (b.c:1)\"]\n    \
+         n4[\"main\"]\n    \
+         n2 --> n1\n    \
+         n3 --> n0\n    \
+         n4 --> n2\n    \
+         n4 -.-> n3\n"
+    );
+}
+
+#[test]
+fn csv() {
+    assert_eq!(
+        write(&result(), Format::Csv),
+        "caller_id,caller,callee_id,callee,kinds,sites\n\
+         init@a.c,init,free,free,call,a.c:11\n\
+         init@b.c,init,_Z4lessIiiEbv,\"less<int, int>\",call,\n\
+         main,main,init@a.c,init,call,a.c:2\n\
+         main,main,init@b.c,init,address,a.c:3\n"
+    );
+}
+
+#[test]
+fn tree() {
+    assert_eq!(
+        write(&result(), Format::Tree),
+        "main\n  init (a.c:10)\n    free\n  init (b.c:1)\n    less<int, int>\n"
+    );
+
+    // Symbols are expanded once, and cycles without a root still show up
+    let cycle = cfg(r#"{"map": {
+        "main": {"name": "main", "ranges": [], "children": {"a": [{"kind": "Call", "range": null}], "b": [{"kind": "Call", "range": null}]}},
+        "a": {"name": "a", "ranges": [], "children": {"b": [{"kind": "Call", "range": null}]}},
+        "b": {"name": "b", "ranges": [], "children": {"b": [{"kind": "Call", "range": null}]}},
+        "ping": {"name": "ping", "ranges": [], "children": {"pong": [{"kind": "Call", "range": null}]}},
+        "pong": {"name": "pong", "ranges": [], "children": {"ping": [{"kind": "Call", "range": null}]}}
+    }}"#);
+    assert_eq!(
+        write(&query(&cycle, "@all {}"), Format::Tree),
+        "main\n  a\n    b\n      b ...\n  b ...\nping\n  pong\n    ping ...\n"
+    );
+}
+
+#[test]
+fn deep_tree() {
+    // A call chain deeper than a small thread stack could recurse
+    const DEPTH: usize = 5000;
+    let id = |n: usize| SymbolId::new(format!("f{}", n));
+    let result = QueryResult {
+        symbols: (0..DEPTH)
+            .map(|n| ResultSymbol {
+                id: id(n),
+                name: id(n).to_string(),
+                location: None,
+                group: None,
+            })
+            .collect(),
+        edges: (1..DEPTH)
+            .map(|n| ResultEdge {
+                from: id(n - 1),
+                to: id(n),
+                kinds: EdgeKinds::calls(),
+                sites: vec![],
+                weight: 1,
+            })
+            .collect(),
+        collapsed: false,
+    };
+
+    let tree = std::thread::Builder::new()
+        .stack_size(64 * 1024)
+        .spawn(move || write(&result, Format::Tree))
+        .unwrap()
+        .join()
+        .unwrap();
+    assert_eq!(tree.lines().count(), DEPTH);
+    assert_eq!(tree.lines().last().unwrap(), format!("{}f{}", "  ".repeat(DEPTH - 1), DEPTH - 1));
+}
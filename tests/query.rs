@@ -0,0 +1,203 @@
+mod common;
+
+use askl::cfg::EdgeKinds;
+use askl::symbols::SymbolId;
+use askl::Askl;
+use common::{cfg, edges, symbol_ids};
+
+/// `free` is a library function, only known as a callee
+const INDEX: &str = r#"{"map": {
+    "main": {"name": "main", "ranges": [], "children": {"foo": [{"kind": "Call", "range": null}], "bar": [{"kind": "Call", "range": null}]}},
+    "foo": {"name": "foo", "ranges": [], "children": {"free": [{"kind": "Call", "range": null}], "baz": [{"kind": "Call", "range": null}]}},
+    "bar": {"name": "bar", "ranges": [], "children": {"foo": [{"kind": "AddressTaken", "range": null}]}},
+    "baz": {"name": "baz", "ranges": [], "children": {}}
+}}"#;
+
+fn pairs(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+    pairs.iter().map(|(from, to)| (from.to_string(), to.to_string())).collect()
+}
+
+#[test]
+fn callers() {
+    let cfg = cfg(INDEX);
+    assert_eq!(symbol_ids(&cfg, r#""foo" ^{}"#), ["bar", "foo", "main"]);
+    assert_eq!(edges(&cfg, r#""foo" ^{}"#), pairs(&[("bar", "foo"), ("main", "foo")]));
+    assert_eq!(edges(&cfg, r#""foo" ^{"bar"}"#), pairs(&[("bar", "foo")]));
+    // Symbols called by main
+    assert_eq!(edges(&cfg, r#"^{"main"}"#), pairs(&[("main", "bar"), ("main", "foo")]));
+    assert_eq!(symbol_ids(&cfg, r#""main" ^{}"#), Vec::<String>::new());
+}
+
+#[test]
+fn callee_only() {
+    let cfg = cfg(INDEX);
+    assert_eq!(symbol_ids(&cfg, r#""free""#), ["free"]);
+    assert_eq!(edges(&cfg, r#""free" ^{}"#), pairs(&[("foo", "free")]));
+    assert_eq!(edges(&cfg, r#""main" {"foo" {"free"}}"#), pairs(&[("foo", "free"), ("main", "foo")]));
+}
+
+#[test]
+fn closures() {
+    let cfg = cfg(INDEX);
+    assert_eq!(
+        edges(&cfg, r#""main" {"baz"}*"#),
+        pairs(&[("bar", "foo"), ("foo", "baz"), ("main", "bar"), ("main", "foo")])
+    );
+    assert_eq!(symbol_ids(&cfg, r#""main" {"baz"}*2"#), ["bar", "baz", "foo", "main"]);
+    // baz is two calls away from main
+    assert_eq!(symbol_ids(&cfg, r#""main" {"baz"}*1"#), Vec::<String>::new());
+    // Callers of baz through any number of calls
+    assert_eq!(symbol_ids(&cfg, r#""baz" ^{"main"}*"#), ["bar", "baz", "foo", "main"]);
+    assert!(Askl::new(r#""main" {"baz"}*0"#).is_err());
+
+    // Recursion does not make the walk loop
+    let recursive = common::cfg(r#"{"map": {
+        "ping": {"name": "ping", "ranges": [], "children": {"pong": [{"kind": "Call", "range": null}]}},
+        "pong": {"name": "pong", "ranges": [], "children": {"ping": [{"kind": "Call", "range": null}], "exit": [{"kind": "Call", "range": null}]}}
+    }}"#);
+    assert_eq!(symbol_ids(&recursive, r#""ping" {"exit"}*"#), ["exit", "ping", "pong"]);
+}
+
+#[test]
+fn name_patterns() {
+    let cfg = cfg(INDEX);
+    assert_eq!(symbol_ids(&cfg, r#"@filter(regex="^ba")"#), ["bar", "baz"]);
+    // Regexes are not anchored
+    assert_eq!(symbol_ids(&cfg, r#"@filter(regex="a")"#), ["bar", "baz", "main"]);
+    assert_eq!(symbol_ids(&cfg, r#"@filter(glob="ba?")"#), ["bar", "baz"]);
+    assert_eq!(symbol_ids(&cfg, r#"@filter(glob="f*")"#), ["foo", "free"]);
+    assert_eq!(symbol_ids(&cfg, r#"@filter(name="MAIN")"#), Vec::<String>::new());
+    assert_eq!(symbol_ids(&cfg, r#"@filter(name="MAIN", ignore_case="true")"#), ["main"]);
+    assert_eq!(symbol_ids(&cfg, r#"@filter(regex="^MA", ignore_case="true")"#), ["main"]);
+    assert_eq!(symbol_ids(&cfg, r#"@filter(glob="B*", ignore_case="true")"#), ["bar", "baz"]);
+    assert_eq!(edges(&cfg, r#""main" {@filter(glob="b*")}"#), pairs(&[("main", "bar")]));
+
+    assert!(Askl::new(r#"@filter(name="main", regex="main")"#).is_err());
+    assert!(Askl::new(r#"@filter(ignore_case="true")"#).is_err());
+    assert!(Askl::new(r#"@filter(regex="(")"#).is_err());
+    assert!(Askl::new(r#"@filter(glob="[")"#).is_err());
+    assert!(Askl::new(r#"@filter(name="main", ignore_case="yes")"#).is_err());
+}
+
+#[test]
+fn negation() {
+    let cfg = cfg(INDEX);
+    assert_eq!(symbol_ids(&cfg, r#""main" !{"free"}"#), ["main"]);
+    assert_eq!(symbol_ids(&cfg, r#""foo" !{"free"}"#), Vec::<String>::new());
+    // Functions without callers
+    assert_eq!(symbol_ids(&cfg, r#"@all !^{}"#), ["main"]);
+    // Functions not calling free through any number of calls
+    assert_eq!(symbol_ids(&cfg, r#"@all !{"free"}*"#), ["baz"]);
+    assert_eq!(symbol_ids(&cfg, r#"@not(glob="*a*")"#), ["foo", "free"]);
+    assert_eq!(edges(&cfg, r#""main" {@exclude(name="foo")}"#), pairs(&[("main", "bar")]));
+}
+
+#[test]
+fn paths() {
+    let cfg = cfg(INDEX);
+    let id = |name: &str| SymbolId::new(name.into());
+    let calls = EdgeKinds::calls();
+    let shortest = cfg.shortest_path(&[id("main")], &[id("baz")], calls).unwrap();
+    assert_eq!(shortest.0, [(id("main"), id("foo")), (id("foo"), id("baz"))]);
+    assert!(cfg.shortest_path(&[id("baz")], &[id("main")], calls).is_none());
+    // A function reaches itself without any call
+    assert!(cfg.shortest_path(&[id("main")], &[id("main")], calls).unwrap().0.is_empty());
+    assert!(cfg.all_simple_paths(&[id("main")], &[id("main")], calls, None).0.is_empty());
+
+    let all = |max_calls| cfg.all_simple_paths(&[id("main")], &[id("baz")], EdgeKinds::all(), max_calls).0;
+    let every = [(id("bar"), id("foo")), (id("foo"), id("baz")), (id("main"), id("bar")), (id("main"), id("foo"))];
+    assert_eq!(all(None), every);
+    assert_eq!(all(Some(3)), every);
+    assert_eq!(all(Some(2)), [(id("foo"), id("baz")), (id("main"), id("foo"))]);
+    assert!(all(Some(1)).is_empty());
+    assert!(all(Some(0)).is_empty());
+
+    // bar only takes the address of foo, so it never ends up calling baz
+    assert!(cfg.shortest_path(&[id("bar")], &[id("baz")], calls).is_none());
+    assert!(cfg.all_simple_paths(&[id("bar")], &[id("baz")], calls, None).0.is_empty());
+    assert_eq!(
+        cfg.all_simple_paths(&[id("main")], &[id("baz")], calls, None).0,
+        [(id("foo"), id("baz")), (id("main"), id("foo"))]
+    );
+    let through_address = cfg.shortest_path(&[id("bar")], &[id("baz")], EdgeKinds::all()).unwrap();
+    assert_eq!(through_address.0, [(id("bar"), id("foo")), (id("foo"), id("baz"))]);
+}
+
+#[test]
+fn edge_kinds() {
+    let cfg = cfg(INDEX);
+    assert_eq!(edges(&cfg, r#""bar" [address]{}"#), pairs(&[("bar", "foo")]));
+    assert_eq!(symbol_ids(&cfg, r#""bar" [call]{}"#), Vec::<String>::new());
+    assert_eq!(edges(&cfg, r#""foo" ^[!address]{}"#), pairs(&[("main", "foo")]));
+    assert_eq!(edges(&cfg, r#""foo" ^[call, address]{}"#), pairs(&[("bar", "foo"), ("main", "foo")]));
+    assert!(Askl::new(r#""foo" [jump]{}"#).is_err());
+}
+
+#[test]
+fn locations() {
+    // `send` is declared in a header and defined in a source file, `alloc` is
+    // only declared
+    let cfg = cfg(r#"{"map": {
+        "main": {"name": "main", "ranges": [{"begin": {"file": "./src/main.c", "line": 1, "col": 1}, "end": {"file": "./src/main.c", "line": 5, "col": 1}}],
+                 "children": {"send": [{"kind": "Call", "range": null}], "alloc": [{"kind": "Call", "range": null}]}},
+        "send": {"name": "send", "ranges": [{"begin": {"file": "include/net.h", "line": 3, "col": 1}, "end": {"file": "include/net.h", "line": 3, "col": 20}},
+                                            {"begin": {"file": "src/net/send.c", "line": 1, "col": 1}, "end": {"file": "src/net/send.c", "line": 5, "col": 1}}],
+                 "definition": {"begin": {"file": "src/net/send.c", "line": 1, "col": 1}, "end": {"file": "src/net/send.c", "line": 5, "col": 1}},
+                 "children": {}},
+        "alloc": {"name": "alloc", "ranges": [{"begin": {"file": "include/mm.h", "line": 1, "col": 1}, "end": {"file": "include/mm.h", "line": 1, "col": 20}}],
+                  "children": {}}
+    }}"#);
+    // Symbols are found where they are defined, not where they are declared
+    assert_eq!(symbol_ids(&cfg, r#"@file(path="include/*.h")"#), ["alloc"]);
+    assert_eq!(symbol_ids(&cfg, r#"@dir(path="include")"#), ["alloc"]);
+    assert_eq!(symbol_ids(&cfg, r#"@file(path="src/net/send.c")"#), ["send"]);
+    assert_eq!(symbol_ids(&cfg, r#"@dir(path="./src/")"#), ["main", "send"]);
+    // * does not cross directories
+    assert_eq!(symbol_ids(&cfg, r#"@file(path="src/*.c")"#), ["main"]);
+    assert_eq!(symbol_ids(&cfg, r#"@file(path="src/**/*.c")"#), ["main", "send"]);
+    assert!(Askl::new(r#"@file(name="main.c")"#).is_err());
+}
+
+#[test]
+fn result() {
+    let cpp = cfg(r#"{"map": {
+        "main": {"name": "main", "ranges": [{"begin": {"file": "a.cpp", "line": 1, "col": 1}, "end": {"file": "a.cpp", "line": 9, "col": 1}}],
+                 "children": {"_ZN2ns3Foo3runEv": [{"kind": "Call", "range": {"begin": {"file": "a.cpp", "line": 2, "col": 5}, "end": {"file": "a.cpp", "line": 2, "col": 9}}},
+                                                   {"kind": "AddressTaken", "range": {"begin": {"file": "a.cpp", "line": 3, "col": 5}, "end": {"file": "a.cpp", "line": 3, "col": 9}}}],
+                              "free": [{"kind": "Call", "range": null}]}},
+        "_ZN2ns3Foo3runEv": {"name": "run", "scope": "ns::Foo", "ranges": [], "children": {}}
+    }}"#);
+
+    // A query is compiled once and can run on any index
+    let askl = Askl::new(r#""main" {"ns::Foo::run"; "free"}"#).unwrap();
+    let result = askl.run(&cpp);
+    let names: Vec<&str> = result.symbols.iter().map(|symbol| symbol.name.as_str()).collect();
+    assert_eq!(names, ["ns::Foo::run", "free", "main"]);
+    assert_eq!(result.get_symbol(&SymbolId::new("main".into())).unwrap().location.as_ref().unwrap().line, 1);
+    assert!(result.get_symbol(&SymbolId::new("free".into())).unwrap().location.is_none());
+    assert!(result.get_symbol(&SymbolId::new("missing".into())).is_none());
+
+    let run = &result.edges.iter().find(|edge| edge.to.as_str() == "_ZN2ns3Foo3runEv").unwrap();
+    assert_eq!(run.kinds.iter().map(|kind| kind.name()).collect::<Vec<_>>(), ["call", "address"]);
+    assert_eq!((run.sites.len(), run.weight), (2, 2));
+    // References are kept even where the index does not know their range
+    let free = &result.edges.iter().find(|edge| edge.to.as_str() == "free").unwrap();
+    assert_eq!((free.sites.len(), free.weight), (1, 1));
+    assert!(free.sites[0].range.is_none());
+
+    assert!(askl.run(&cfg(INDEX)).symbols.is_empty());
+    let err = Askl::new(r#""main" {"#).unwrap_err().to_string();
+    assert!(err.contains("1:9"), "{}", err);
+}
+
+#[test]
+fn line_breaks() {
+    let cfg = cfg(INDEX);
+    // Blank lines between statements do not match every symbol
+    assert_eq!(edges(&cfg, "\"main\" {\n\"foo\"\n}"), pairs(&[("main", "foo")]));
+    assert_eq!(edges(&cfg, "\"main\" {\n\n\"foo\";\n}\n"), pairs(&[("main", "foo")]));
+    assert_eq!(symbol_ids(&cfg, "\n\"baz\"\n\n\"bar\"\n"), ["bar", "baz"]);
+    // A scope with nothing but line breaks still matches everything
+    assert_eq!(edges(&cfg, "\"main\" {\n}"), pairs(&[("main", "bar"), ("main", "foo")]));
+    assert_eq!(edges(&cfg, "\"main\" {}"), pairs(&[("main", "bar"), ("main", "foo")]));
+}
@@ -0,0 +1,156 @@
+mod common;
+
+use common::tmp_path;
+use serde_json::{json, Value};
+use std::io::{BufRead, BufReader, Write};
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+#[cfg(unix)]
+use std::time::Duration;
+
+const INDEX: &str = r#"{"map": {
+    "main": {"name": "main", "ranges": [], "children": {"foo": [{"kind": "Call", "range": null}], "bar": [{"kind": "Call", "range": null}]}},
+    "foo": {"name": "foo", "ranges": [], "children": {"free": [{"kind": "Call", "range": null}]}},
+    "bar": {"name": "bar", "ranges": [], "children": {"foo": [{"kind": "AddressTaken", "range": null}]}}
+}}"#;
+
+fn askl(args: &[&str]) -> Child {
+    Command::new(env!("CARGO_BIN_EXE_askl"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap()
+}
+
+struct Client<W: Write, R: BufRead> {
+    input: W,
+    output: R,
+    id: u64,
+}
+
+impl<W: Write, R: BufRead> Client<W, R> {
+    fn call(&mut self, method: &str, params: Value) -> Value {
+        self.id += 1;
+        let request = json!({"jsonrpc": "2.0", "id": self.id, "method": method, "params": params});
+        writeln!(self.input, "{}", request).unwrap();
+        self.input.flush().unwrap();
+
+        let mut line = String::new();
+        self.output.read_line(&mut line).unwrap();
+        let response: Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(response["id"], self.id);
+        response
+    }
+
+    fn result(&mut self, method: &str, params: Value) -> Value {
+        let response = self.call(method, params);
+        assert!(response.get("error").is_none(), "{}", response);
+        response["result"].clone()
+    }
+}
+
+fn stdio_client(child: &mut Child) -> Client<ChildStdin, BufReader<ChildStdout>> {
+    Client {
+        input: child.stdin.take().unwrap(),
+        output: BufReader::new(child.stdout.take().unwrap()),
+        id: 0,
+    }
+}
+
+fn ids(values: &Value, key: &str) -> Vec<String> {
+    let mut ids: Vec<String> = values
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|value| value[key].as_str().unwrap().to_string())
+        .collect();
+    ids.sort();
+    ids
+}
+
+fn edges(graph: &Value) -> Vec<(String, String)> {
+    graph["edges"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|edge| {
+            (
+                edge["from"].as_str().unwrap().to_string(),
+                edge["to"].as_str().unwrap().to_string(),
+            )
+        })
+        .collect()
+}
+
+#[test]
+fn stdio() {
+    let index = tmp_path("server-stdio.json");
+    std::fs::write(&index, INDEX).unwrap();
+    let mut child = askl(&["-i", index.to_str().unwrap(), "serve"]);
+    let mut client = stdio_client(&mut child);
+
+    let graph = client.result("query", json!({"query": "\"main\" {}"}));
+    assert_eq!(ids(&graph["nodes"], "id"), ["bar", "foo", "main"]);
+    assert_eq!(edges(&graph), [("main".into(), "bar".into()), ("main".into(), "foo".into())]);
+
+    let tree = client.result("query", json!({"query": "\"main\" {}", "format": "tree"}));
+    assert_eq!(tree, "main\n  bar\n  foo\n");
+
+    let symbols = client.result("symbol", json!({"name": "foo"}));
+    assert_eq!(ids(&symbols, "id"), ["foo"]);
+    assert_eq!(symbols[0]["linkage"], "External");
+
+    let graph = client.result("callers", json!({"name": "foo"}));
+    assert_eq!(ids(&graph["nodes"], "id"), ["bar", "foo", "main"]);
+    let graph = client.result("callers", json!({"name": "foo", "kinds": ["call"]}));
+    assert_eq!(edges(&graph), [("main".into(), "foo".into())]);
+    let graph = client.result("callees", json!({"id": "foo"}));
+    assert_eq!(edges(&graph), [("foo".into(), "free".into())]);
+
+    let response = client.call("callees", json!({"name": "nobody"}));
+    assert!(response["error"]["message"].as_str().unwrap().contains("nobody"));
+    let response = client.call("query", json!({"query": "{"}));
+    assert_eq!(response["error"]["code"], -32602);
+
+    std::fs::write(&index, r#"{"map": {"solo": {"name": "solo", "ranges": [], "children": {}}}}"#).unwrap();
+    assert_eq!(client.result("reload", json!({})), json!({"symbols": 1}));
+    let graph = client.result("query", json!({"query": "\"solo\""}));
+    assert_eq!(ids(&graph["nodes"], "id"), ["solo"]);
+
+    drop(client);
+    assert!(child.wait().unwrap().success());
+}
+
+#[cfg(unix)]
+#[test]
+fn socket() {
+    let index = tmp_path("server-socket.json");
+    std::fs::write(&index, INDEX).unwrap();
+    let socket = tmp_path("server.sock");
+    let _ = std::fs::remove_file(&socket);
+    let mut child = askl(&["-i", index.to_str().unwrap(), "serve", "--socket", socket.to_str().unwrap()]);
+
+    let mut stream = None;
+    for _ in 0..100 {
+        if let Ok(connected) = UnixStream::connect(&socket) {
+            stream = Some(connected);
+            break;
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    }
+    let stream = stream.expect("server did not open the socket");
+
+    let mut client = Client {
+        input: stream.try_clone().unwrap(),
+        output: BufReader::new(stream),
+        id: 0,
+    };
+    let graph = client.result("callees", json!({"name": "main"}));
+    assert_eq!(ids(&graph["nodes"], "id"), ["bar", "foo", "main"]);
+
+    child.kill().unwrap();
+    child.wait().unwrap();
+}
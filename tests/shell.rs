@@ -0,0 +1,30 @@
+use askl::shell::{is_complete, Names};
+
+#[test]
+fn continued_queries() {
+    assert!(is_complete(r#""main" {}"#));
+    assert!(!is_complete(r#""main" {"#));
+    assert!(!is_complete(r#""main" {"foo" {}"#));
+    // Extra closing braces are for the parser to report
+    assert!(is_complete(r#""main" }"#));
+    // Braces within names do not count
+    assert!(is_complete(r#""operator{" {}"#));
+    assert!(!is_complete(r#""main}" {"#));
+    assert!(!is_complete(r#""main {}"#));
+}
+
+#[test]
+fn completion() {
+    let names: Vec<String> = ["parse", "main", "ns::Foo::run", "parse_args", "main"].map(String::from).into();
+    let names = Names::new(names);
+    // At the end, in the middle and at the start of a name
+    let line = r#""main" {pars"#;
+    assert_eq!(names.complete(line, line.len()), (8, vec!["parse".into(), "parse_args".into()]));
+    assert_eq!(names.complete(line, 10), (8, vec!["parse".into(), "parse_args".into()]));
+    assert_eq!(names.complete(line, 8), (8, vec![]));
+    // Names are completed at the start of the line, and within quotes with
+    // their scopes
+    assert_eq!(names.complete("ma", 2), (0, vec!["main".into()]));
+    assert_eq!(names.complete(r#"{"ns::F"#, 7), (2, vec!["ns::Foo::run".into()]));
+    assert_eq!(names.complete("run", 3), (0, vec![]));
+}